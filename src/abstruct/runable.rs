@@ -1,10 +1,15 @@
 use anyhow::Result;
 use async_trait::async_trait;
 
-use crate::context::Context;
+use crate::{context::Context, supervisor::RestartPolicy};
 
 #[async_trait]
 pub trait Runable: Send + 'static {
     fn name(&self) -> &'static str;
     async fn run(&mut self, ctx: Context) -> Result<()>;
+
+    /// 任务退出后的重启策略，默认不重启
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::never()
+    }
 }
@@ -0,0 +1,396 @@
+//! 消息告警
+//!
+//! [`LiveMirror`](super::LiveMirror)收到新消息后按[`AlertRule`]匹配，命中后为规则中的每个webhook
+//! 记录一行`alert`。每条规则有频率限制与文本去重窗口，通过的记录为`pending`，由[`AlertDelivery`]
+//! 将[`AlertPayload`]以JSON POST到webhook，投递与重试结果写回`alert`表。
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, VecDeque},
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use grammers_client::types::Message as RawMessage;
+use reqwest::header::CONTENT_TYPE;
+use sea_orm::ActiveEnum;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Notify, time::Instant};
+use tracing::{info, warn};
+
+use crate::{
+    filter::Filter,
+    metrics,
+    supervisor::RestartPolicy,
+    types::{
+        alert::{self, AlertStatus},
+        MessageExt,
+    },
+    Context, PrintError, Runable,
+};
+
+/// 单次投递的最多尝试次数，间隔从1秒起翻倍
+pub const MAX_ATTEMPTS: i32 = 3;
+/// 没有新命中时重新检查待投递记录的间隔
+const IDLE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertRule {
+    /// 记录在`alert`表与载荷中
+    pub name: String,
+    /// 关键词、正则、聊天集合、是否含链接等均由过滤器表达
+    pub filter: Filter,
+    pub webhooks: Vec<String>,
+    /// 每分钟最多投递的消息数，0为不限
+    #[serde(default)]
+    pub max_per_minute: usize,
+    /// 此秒数内相同文本只投递一次，0为不去重
+    #[serde(default)]
+    pub dedup_secs: u64,
+}
+
+/// POST到webhook的JSON
+#[derive(Debug, Clone, Serialize)]
+pub struct AlertPayload {
+    pub rule: String,
+    pub chat_id: i64,
+    pub chat_name: String,
+    pub chat_username: Option<String>,
+    pub msg_id: i32,
+    pub sender_id: Option<i64>,
+    pub text: String,
+    pub date: DateTime<Utc>,
+    /// 公开聊天的消息链接
+    pub url: Option<String>,
+    pub links: Vec<String>,
+}
+
+impl AlertPayload {
+    pub fn new(rule: &AlertRule, msg: &RawMessage) -> Self {
+        let chat = msg.chat();
+        let username = chat.username().map(|s| s.to_string());
+        Self {
+            rule: rule.name.clone(),
+            chat_id: chat.id(),
+            chat_name: chat.name().to_string(),
+            url: username
+                .as_ref()
+                .map(|username| format!("https://t.me/{username}/{}", msg.id())),
+            chat_username: username,
+            msg_id: msg.id(),
+            sender_id: msg.sender().map(|s| s.id()),
+            text: msg.text().to_string(),
+            date: msg.date(),
+            links: MessageExt::from(msg)
+                .links()
+                .into_iter()
+                .map(|l| l.link)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct RuleState {
+    /// 最近一分钟内的投递时间
+    sent: VecDeque<Instant>,
+    /// 文本哈希与其投递时间
+    seen: HashMap<u64, Instant>,
+}
+
+impl RuleState {
+    /// 去重与频率限制，通过时记入状态并返回[`AlertStatus::Pending`]
+    fn admit(&mut self, rule: &AlertRule, text: &str, now: Instant) -> AlertStatus {
+        let hash = (rule.dedup_secs > 0 && !text.is_empty()).then(|| {
+            let mut hasher = DefaultHasher::new();
+            text.hash(&mut hasher);
+            hasher.finish()
+        });
+        if let Some(hash) = hash {
+            let window = Duration::from_secs(rule.dedup_secs);
+            self.seen.retain(|_, t| now.duration_since(*t) < window);
+            if self.seen.contains_key(&hash) {
+                return AlertStatus::Duplicate;
+            }
+        }
+        if rule.max_per_minute > 0 {
+            let window = Duration::from_secs(60);
+            while self
+                .sent
+                .front()
+                .is_some_and(|t| now.duration_since(*t) >= window)
+            {
+                self.sent.pop_front();
+            }
+            if self.sent.len() >= rule.max_per_minute {
+                return AlertStatus::RateLimited;
+            }
+            self.sent.push_back(now);
+        }
+        if let Some(hash) = hash {
+            self.seen.insert(hash, now);
+        }
+        AlertStatus::Pending
+    }
+}
+
+#[derive(Debug)]
+pub struct Alerter {
+    rules: Vec<AlertRule>,
+    /// 与`rules`一一对应
+    state: Mutex<Vec<RuleState>>,
+    /// 有新的待投递记录时唤醒[`AlertDelivery`]
+    notify: Arc<Notify>,
+}
+
+impl Alerter {
+    pub fn new(rules: Vec<AlertRule>) -> Self {
+        let state = rules.iter().map(|_| RuleState::default()).collect();
+        Self {
+            rules,
+            state: Mutex::new(state),
+            notify: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// 投递此告警器记录的命中，需作为后台任务运行
+    pub fn delivery(&self) -> AlertDelivery {
+        AlertDelivery::new(self.notify.clone())
+    }
+
+    fn admit(&self, index: usize, text: &str) -> AlertStatus {
+        let mut state = self.state.lock().expect("告警状态锁中毒");
+        state[index].admit(&self.rules[index], text, Instant::now())
+    }
+
+    /// 匹配全部规则并记录命中，投递由[`AlertDelivery`]进行
+    pub async fn check(&self, ctx: &Context, msg: &RawMessage) -> Result<()> {
+        let mut pending = false;
+        for (index, rule) in self.rules.iter().enumerate() {
+            if !rule.filter.matches(msg) {
+                continue;
+            }
+            let (chat_id, msg_id) = (msg.chat().id(), msg.id());
+            let payload = serde_json::to_value(AlertPayload::new(rule, msg))?;
+            // 只有确实写入了新记录才计入去重与频率限制，同一消息的各webhook共用一次结果
+            let mut status = None;
+            for webhook in &rule.webhooks {
+                let Some(alert) = ctx
+                    .persist
+                    .put_alert(
+                        &rule.name,
+                        webhook,
+                        (chat_id, msg_id),
+                        payload.clone(),
+                        || *status.get_or_insert_with(|| self.admit(index, msg.text())),
+                    )
+                    .await?
+                else {
+                    continue;
+                };
+                if alert.status == AlertStatus::Pending {
+                    pending = true;
+                } else {
+                    metrics::ALERTS
+                        .with_label_values(&[&rule.name, &alert.status.to_value()])
+                        .inc();
+                }
+            }
+            match status {
+                Some(status) => info!(rule = rule.name, chat_id, msg_id, ?status, "命中告警规则"),
+                None => info!(rule = rule.name, chat_id, msg_id, "告警已记录过"),
+            }
+        }
+        if pending {
+            self.notify.notify_one();
+        }
+        Ok(())
+    }
+}
+
+/// 投递`pending`状态的告警，启动时接续上次未完成的投递
+pub struct AlertDelivery {
+    client: reqwest::Client,
+    notify: Arc<Notify>,
+}
+
+#[async_trait]
+impl Runable for AlertDelivery {
+    fn name(&self) -> &'static str {
+        "告警投递"
+    }
+
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::on_failure()
+    }
+
+    async fn run(&mut self, ctx: Context) -> Result<()> {
+        loop {
+            for alert in ctx.persist.find_pending_alerts().await? {
+                if ctx.cancel.is_cancelled() {
+                    return Ok(());
+                }
+                self.deliver(&ctx, alert).await.ok_or_warn();
+            }
+            tokio::select! {
+                _ = self.notify.notified() => (),
+                _ = tokio::time::sleep(IDLE_INTERVAL) => (),
+                _ = ctx.cancel.cancelled() => return Ok(()),
+            }
+        }
+    }
+}
+
+impl AlertDelivery {
+    fn new(notify: Arc<Notify>) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("HTTP客户端初始化失败"),
+            notify,
+        }
+    }
+
+    /// 收到退出信号时停止重试，记录保持`pending`，下次启动时继续
+    async fn deliver(&self, ctx: &Context, alert: alert::Model) -> Result<()> {
+        let body = serde_json::to_vec(&alert.payload)?;
+        let mut attempts = alert.attempts;
+        loop {
+            attempts += 1;
+            let ret = self
+                .client
+                .post(&alert.webhook)
+                .header(CONTENT_TYPE, "application/json")
+                .body(body.clone())
+                .send()
+                .await
+                .and_then(|resp| resp.error_for_status());
+            let (status, error) = match &ret {
+                Ok(_) => (AlertStatus::Delivered, None),
+                Err(e) if attempts < MAX_ATTEMPTS => (AlertStatus::Pending, Some(e.to_string())),
+                Err(e) => (AlertStatus::Failed, Some(e.to_string())),
+            };
+            ctx.persist
+                .set_alert_attempt(alert.id, attempts, status, error)
+                .await?;
+            match ret {
+                Ok(_) => {
+                    info!(
+                        rule = alert.rule,
+                        webhook = alert.webhook,
+                        attempts,
+                        "告警已投递"
+                    );
+                    metrics::ALERTS
+                        .with_label_values(&[&alert.rule, &status.to_value()])
+                        .inc();
+                    return Ok(());
+                }
+                Err(e) if status == AlertStatus::Failed => {
+                    metrics::ALERTS
+                        .with_label_values(&[&alert.rule, &status.to_value()])
+                        .inc();
+                    bail!(
+                        "告警投递失败 rule={} webhook={} attempts={attempts} >> {e}",
+                        alert.rule,
+                        alert.webhook
+                    );
+                }
+                Err(e) => {
+                    let delay = Duration::from_secs(1 << (attempts - 1).clamp(0, 6));
+                    warn!(
+                        rule = alert.rule,
+                        webhook = alert.webhook,
+                        attempts,
+                        ?delay,
+                        "告警投递失败，等待重试 >> {e}"
+                    );
+                    tokio::select! {
+                        _ = tokio::time::sleep(delay) => (),
+                        _ = ctx.cancel.cancelled() => return Ok(()),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(max_per_minute: usize, dedup_secs: u64) -> AlertRule {
+        AlertRule {
+            name: "test".to_string(),
+            filter: Filter::default(),
+            webhooks: Vec::new(),
+            max_per_minute,
+            dedup_secs,
+        }
+    }
+
+    #[test]
+    fn rate_limit_slides_with_window() {
+        let rule = rule(2, 0);
+        let mut state = RuleState::default();
+        let start = Instant::now();
+        assert_eq!(state.admit(&rule, "a", start), AlertStatus::Pending);
+        assert_eq!(state.admit(&rule, "b", start), AlertStatus::Pending);
+        assert_eq!(state.admit(&rule, "c", start), AlertStatus::RateLimited);
+        // 被限制的不占用名额
+        let later = start + Duration::from_secs(59);
+        assert_eq!(state.admit(&rule, "d", later), AlertStatus::RateLimited);
+        let later = start + Duration::from_secs(60);
+        assert_eq!(state.admit(&rule, "e", later), AlertStatus::Pending);
+        assert_eq!(state.admit(&rule, "f", later), AlertStatus::Pending);
+        assert_eq!(state.admit(&rule, "g", later), AlertStatus::RateLimited);
+    }
+
+    #[test]
+    fn dedup_within_window() {
+        let rule = rule(0, 10);
+        let mut state = RuleState::default();
+        let start = Instant::now();
+        assert_eq!(state.admit(&rule, "a", start), AlertStatus::Pending);
+        assert_eq!(state.admit(&rule, "b", start), AlertStatus::Pending);
+        let later = start + Duration::from_secs(9);
+        assert_eq!(state.admit(&rule, "a", later), AlertStatus::Duplicate);
+        let later = start + Duration::from_secs(10);
+        assert_eq!(state.admit(&rule, "a", later), AlertStatus::Pending);
+        // 空文本不去重
+        assert_eq!(state.admit(&rule, "", later), AlertStatus::Pending);
+        assert_eq!(state.admit(&rule, "", later), AlertStatus::Pending);
+    }
+
+    #[test]
+    fn rate_limited_text_is_not_remembered() {
+        let rule = rule(1, 60);
+        let mut state = RuleState::default();
+        let start = Instant::now();
+        assert_eq!(state.admit(&rule, "a", start), AlertStatus::Pending);
+        assert_eq!(state.admit(&rule, "b", start), AlertStatus::RateLimited);
+        let later = start + Duration::from_secs(30);
+        assert_eq!(state.admit(&rule, "a", later), AlertStatus::Duplicate);
+        // 上一次被限制，限流窗口过后同一文本仍可投递
+        let later = start + Duration::from_secs(60);
+        assert_eq!(state.admit(&rule, "b", later), AlertStatus::Pending);
+    }
+
+    #[test]
+    fn unlimited_rule_always_admits() {
+        let rule = rule(0, 0);
+        let mut state = RuleState::default();
+        let now = Instant::now();
+        for _ in 0..100 {
+            assert_eq!(state.admit(&rule, "a", now), AlertStatus::Pending);
+        }
+    }
+}
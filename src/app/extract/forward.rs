@@ -0,0 +1,242 @@
+//! 转发来源发现与聊天关系记录
+//!
+//! 镜像的消息中，转发来源频道加入[`forward_candidate`]表，由[`ScanForward`]借转发消息
+//! 获取来源频道的access_hash后加入；转发、链接、提及关系写入[`chat_edge`]表。
+
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use grammers_client::{
+    grammers_tl_types as tl,
+    types::{chat::PackedType, Message as RawMessage, PackedChat},
+};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use tracing::{info, warn};
+use url::Url;
+
+use crate::{
+    context::Context,
+    supervisor::RestartPolicy,
+    types::{chat, chat_edge::ChatEdgeKind, forward_candidate},
+    PrintError, Runable, Source,
+};
+
+/// 没有待解析的转发来源或本轮有解析出错时的等待时间
+const IDLE_INTERVAL: Duration = Duration::from_secs(60);
+/// 解析出错达到此次数后放弃该转发来源
+const MAX_ATTEMPTS: i32 = 5;
+
+/// 记录消息中的转发、链接与提及关系，并将转发来源频道加入待解析队列
+///
+/// 关系按次数累计，只应对新写入的消息调用
+pub async fn track_message(ctx: &Context, msg: &RawMessage) -> Result<()> {
+    let chat = msg.chat();
+    let chat_id = chat.id();
+
+    if let Some(tl::enums::MessageFwdHeader::Header(header)) = msg.forward_header() {
+        if let Some(tl::enums::Peer::Channel(channel)) = &header.from_id {
+            let from = channel.channel_id;
+            if from != chat_id {
+                ctx.persist
+                    .put_chat_edge(from, chat_id, ChatEdgeKind::Forward)
+                    .await?;
+                ctx.persist
+                    .put_forward_candidate(forward_candidate::ActiveModel::new(
+                        from,
+                        chat.pack(),
+                        msg.id(),
+                        Source::from_chat(chat_id),
+                    ))
+                    .await?;
+            }
+        }
+    }
+
+    for (username, kind) in usernames(msg) {
+        // 只记录已采集的聊天，避免频繁调用resolve_username
+        let Some(to) = ctx.persist.find_chat(Some(&username)).await? else {
+            continue;
+        };
+        if to.chat_id != chat_id {
+            ctx.persist.put_chat_edge(chat_id, to.chat_id, kind).await?;
+        }
+    }
+    Ok(())
+}
+
+/// 消息中@的用户名与t.me链接中的用户名
+fn usernames(msg: &RawMessage) -> Vec<(String, ChatEdgeKind)> {
+    let mut ret = Vec::new();
+    let words: Vec<u16> = msg.raw.message.encode_utf16().collect();
+    let slice = |offset: i32, length: i32| {
+        words
+            .get(offset as usize..(offset + length) as usize)
+            .and_then(|w| String::from_utf16(w).ok())
+    };
+
+    for ent in msg.raw.entities.iter().flatten() {
+        match ent {
+            tl::enums::MessageEntity::Mention(m) => {
+                if let Some(text) = slice(m.offset, m.length) {
+                    ret.push((
+                        text.trim_start_matches('@').to_string(),
+                        ChatEdgeKind::Mention,
+                    ));
+                }
+            }
+            tl::enums::MessageEntity::Url(u) => {
+                if let Some(name) = slice(u.offset, u.length).as_deref().and_then(tme_username) {
+                    ret.push((name, ChatEdgeKind::Link));
+                }
+            }
+            tl::enums::MessageEntity::TextUrl(u) => {
+                if let Some(name) = tme_username(&u.url) {
+                    ret.push((name, ChatEdgeKind::Link));
+                }
+            }
+            _ => (),
+        }
+    }
+    ret
+}
+
+/// `https://t.me/name/123`中的`name`，邀请链接与私有链接返回None
+fn tme_username(link: &str) -> Option<String> {
+    let url = Url::parse(link)
+        .or_else(|_| Url::parse(&format!("https://{link}")))
+        .ok()?;
+    if !matches!(url.host_str()?, "t.me" | "telegram.me") {
+        return None;
+    }
+    let name = url.path_segments()?.next()?;
+    if name.is_empty() || name.starts_with('+') || matches!(name, "c" | "joinchat" | "s") {
+        return None;
+    }
+    Some(name.to_string())
+}
+
+pub struct ScanForward {}
+
+#[async_trait]
+impl Runable for ScanForward {
+    fn name(&self) -> &'static str {
+        "转发来源扫描"
+    }
+
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::on_failure()
+    }
+
+    async fn run(&mut self, ctx: Context) -> Result<()> {
+        while !ctx.cancel.is_cancelled() {
+            let candidates = forward_candidate::Entity::find()
+                .filter(forward_candidate::Column::Parsed.eq(false))
+                .all(&ctx.persist.db)
+                .await?;
+            if candidates.is_empty() {
+                tokio::select! {
+                    _ = tokio::time::sleep(IDLE_INTERVAL) => continue,
+                    _ = ctx.cancel.cancelled() => break,
+                }
+            }
+
+            let mut count = 0;
+            let mut failed = false;
+            for candidate in candidates {
+                if ctx.cancel.is_cancelled() {
+                    warn!(count, "收到退出信号，停止扫描");
+                    break;
+                }
+                count += 1;
+                let channel_id = candidate.channel_id;
+                // 出错时保留为待解析，稍后重试
+                let Some(packed) = Self::parse_candidate(&ctx, &candidate).await.ok_or_log() else {
+                    failed = true;
+                    warn!(
+                        count,
+                        channel_id,
+                        attempts = candidate.attempts + 1,
+                        "解析转发来源出错"
+                    );
+                    ctx.persist
+                        .add_forward_attempt(channel_id, MAX_ATTEMPTS)
+                        .await?;
+                    continue;
+                };
+                info!(count, channel_id, joined = packed.is_some(), "处理转发来源");
+                ctx.persist
+                    .set_forward_extracted(channel_id, packed)
+                    .await?;
+            }
+            if failed {
+                tokio::select! {
+                    _ = tokio::time::sleep(IDLE_INTERVAL) => (),
+                    _ = ctx.cancel.cancelled() => break,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ScanForward {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    async fn parse_candidate(
+        ctx: &Context,
+        candidate: &forward_candidate::Model,
+    ) -> Result<Option<PackedChat>> {
+        let channel_id = candidate.channel_id;
+        if chat::Entity::find_by_id(channel_id)
+            .one(&ctx.persist.db)
+            .await?
+            .is_some()
+        {
+            info!(channel_id, "已采集过转发来源");
+            return Ok(None);
+        }
+
+        let via_chat = candidate.via_chat()?;
+        ctx.interval.resolve_username.tick().await;
+        let tl::enums::messages::Chats::Chats(chats) = ctx
+            .client
+            .invoke(&tl::functions::channels::GetChannels {
+                id: vec![tl::types::InputChannelFromMessage {
+                    peer: via_chat.to_input_peer(),
+                    msg_id: candidate.via_msg,
+                    channel_id,
+                }
+                .into()],
+            })
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let packed = chats.into_iter().find_map(|chat| match chat {
+            tl::enums::Chat::Channel(c) if c.id == channel_id => Some(PackedChat {
+                ty: if c.megagroup {
+                    PackedType::Megagroup
+                } else if c.gigagroup {
+                    PackedType::Gigagroup
+                } else {
+                    PackedType::Broadcast
+                },
+                id: c.id,
+                access_hash: c.access_hash,
+            }),
+            _ => None,
+        });
+        let Some(packed) = packed else {
+            warn!(channel_id, "无法获取转发来源频道");
+            return Ok(None);
+        };
+
+        let chat = ctx.join_new_chat(packed, candidate.source()).await?;
+        warn!(channel_id, chat_name = chat.name(), "加入转发来源频道");
+        Ok(Some(packed))
+    }
+}
@@ -0,0 +1,119 @@
+//! 手动添加线索，来源记为[`SourceType::Manual`](crate::types::SourceType::Manual)
+
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use grammers_client::types::{Chat, PackedChat};
+use sea_orm::EntityTrait;
+use tracing::{info, warn};
+
+use super::{url_parse::LinkParse, ScanLink};
+use crate::{
+    context::Context,
+    persist::Database,
+    types::{chat, link},
+    PrintError, Source,
+};
+
+/// 加入链接表，等待[`ScanLink`]解析
+pub async fn add_link(db: &Database, link: &str, desc: &str) -> Result<link::Model> {
+    let link = normalize(link)?;
+    let ret = db
+        .put_link(
+            link::Link {
+                link,
+                desc: desc.to_string(),
+            }
+            .to_model(&Source::from_manual()),
+        )
+        .await?;
+    info!(id = ret.id, link = ret.link, "手动添加链接");
+    Ok(ret)
+}
+
+pub async fn add_username(db: &Database, username: &str) -> Result<link::Model> {
+    let username = username.trim().trim_start_matches('@');
+    if username.is_empty() {
+        bail!("用户名为空");
+    }
+    add_link(db, &format!("https://t.me/{username}"), username).await
+}
+
+/// 每行一条链接或`@用户名`，可用制表符分隔描述；空行与`#`开头的行忽略
+pub async fn import_links(db: &Database, path: impl AsRef<Path>) -> Result<usize> {
+    let content = std::fs::read_to_string(path)?;
+    let mut count = 0;
+    for (lineno, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (link, desc) = line.split_once('\t').unwrap_or((line, ""));
+        let ret = if link.starts_with('@') {
+            add_username(db, link).await
+        } else {
+            add_link(db, link, desc).await
+        };
+        if ret.ok_or_warn().is_some() {
+            count += 1;
+        } else {
+            warn!(line = lineno + 1, "无法导入链接");
+        }
+    }
+    Ok(count)
+}
+
+/// 立即解析链接并加入聊天，不等待[`ScanLink`]
+pub async fn join(ctx: &Context, link: &str) -> Result<Option<Chat>> {
+    let model = if link.starts_with('@') {
+        add_username(&ctx.persist, link).await?
+    } else {
+        add_link(&ctx.persist, link, "").await?
+    };
+    // 已采集的聊天直接加入，不再经过解析
+    if let Some(chat) = stored_chat(ctx, &model).await? {
+        info!(
+            link = model.link,
+            chat_id = chat.chat_id,
+            "聊天已采集，重新加入"
+        );
+        return Ok(Some(ctx.join_quited_chat(chat.chat_id).await?));
+    }
+    if let Some(packed) = &model.packed {
+        info!(link = model.link, "链接已解析过，直接加入");
+        let packed = PackedChat::from_hex(packed)?;
+        return Ok(Some(
+            ctx.join_new_chat(packed, Source::from_link(&model)).await?,
+        ));
+    }
+    ScanLink::parse_link(ctx.clone(), model).await
+}
+
+/// 链接指向的聊天已在聊天表中时返回
+async fn stored_chat(ctx: &Context, model: &link::Model) -> Result<Option<chat::Model>> {
+    if let Some(packed) = &model.packed {
+        let packed = PackedChat::from_hex(packed)?;
+        return Ok(chat::Entity::find_by_id(packed.id)
+            .one(&ctx.persist.db)
+            .await?);
+    }
+    let username = match LinkParse::try_from(model.clone())? {
+        LinkParse::ChatMessage(m) => m.username,
+        LinkParse::MaybeChannel(c) => c.username,
+        LinkParse::Invite(_) => return Ok(None),
+    };
+    ctx.persist.find_chat(Some(&username)).await
+}
+
+/// 补全协议，`t.me/xxx`视为`https://t.me/xxx`
+fn normalize(link: &str) -> Result<String> {
+    let link = link.trim();
+    if link.is_empty() {
+        bail!("链接为空");
+    }
+    if link.starts_with("http://") || link.starts_with("https://") {
+        Ok(link.to_string())
+    } else {
+        Ok(format!("https://{link}"))
+    }
+}
@@ -4,23 +4,35 @@ use grammers_client::types::Chat;
 use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
 use tracing::{info, warn};
 
-use crate::{context::Context, types::link, PrintError};
-use crate::{Runable, Source};
+use crate::{context::Context, metrics, types::link, PrintError};
+use crate::{supervisor::RestartPolicy, Runable, Source};
 
 use url_parse::{ChatMessage, Invite, LinkParse, MaybeChannel};
 
+pub mod forward;
+pub mod manual;
 pub mod url_parse;
 
-pub struct ScanLink {}
+pub use forward::ScanForward;
+
+pub struct ScanLink {
+    /// 扫描一遍后退出
+    once: bool,
+}
 
 #[async_trait]
 impl Runable for ScanLink {
     fn name(&self) -> &'static str {
         "链接扫描"
     }
+
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::on_failure()
+    }
+
     async fn run(&mut self, ctx: Context) -> Result<()> {
         let db = &ctx.persist.db;
-        loop {
+        while !ctx.cancel.is_cancelled() {
             // 从数据库获取一批链接
             warn!("开始扫描全部链接");
             let links = link::Entity::find()
@@ -30,57 +42,74 @@ impl Runable for ScanLink {
                 .await?;
             let mut count = 0;
             for link_model in links {
-                let id = link_model.id;
+                if ctx.cancel.is_cancelled() {
+                    warn!(count, "收到退出信号，停止扫描");
+                    break;
+                }
                 count += 1;
                 info!(count, "处理链接");
 
                 // 将链接尝试转换为 (群组名-消息id) 结构
-                let link = LinkParse::try_from(link_model).ok_or_warn();
-                if link.is_none() {
-                    continue;
-                }
-
-                // 转换为link
-                let link = link.unwrap();
-
-                let source = link.source();
-
-                let chat = match link {
-                    LinkParse::ChatMessage(chat_msg) => {
-                        Self::parse_chat_msg(id, chat_msg, ctx.clone(), source)
-                            .await
-                            .ok_or_log()
-                            .flatten()
-                    }
-                    LinkParse::Invite(invite) => Self::parse_invite(id, invite, ctx.clone())
-                        .await
-                        .ok_or_log()
-                        .flatten(),
-                    LinkParse::MaybeChannel(channel) => {
-                        Self::parse_channel(id, channel, ctx.clone())
-                            .await
-                            .ok_or_log()
-                            .flatten()
+                let chat = Self::parse_link(ctx.clone(), link_model).await.ok_or_warn();
+                match chat {
+                    None => continue,
+                    Some(Some(chat)) => {
+                        let username = chat.username();
+                        info!(count, username, "成功解析链接并加入");
                     }
-                };
-                if let Some(chat) = chat {
-                    let username = chat.username();
-                    info!(count, username, "成功解析链接并加入");
-                } else {
-                    info!(count, "未能解析链接");
+                    Some(None) => info!(count, "未能解析链接"),
                 }
             }
             warn!(count, "扫描全部链接完成");
+            if self.once {
+                break;
+            }
         }
+        Ok(())
     }
 }
 
-// ---以下为私有方法---
 impl ScanLink {
     pub fn new() -> Self {
-        Self {}
+        Self { once: false }
+    }
+
+    pub fn once() -> Self {
+        Self { once: true }
+    }
+
+    /// 解析一条链接并加入对应聊天，链接格式错误时返回Err
+    pub async fn parse_link(ctx: Context, link_model: link::Model) -> Result<Option<Chat>> {
+        let id = link_model.id;
+        let link = LinkParse::try_from(link_model)?;
+        let source = link.source();
+        let kind = link.kind();
+
+        let chat = match link {
+            LinkParse::ChatMessage(chat_msg) => Self::parse_chat_msg(id, chat_msg, ctx, source)
+                .await
+                .ok_or_log()
+                .flatten(),
+            LinkParse::Invite(invite) => Self::parse_invite(id, invite, ctx)
+                .await
+                .ok_or_log()
+                .flatten(),
+            LinkParse::MaybeChannel(channel) => Self::parse_channel(id, channel, ctx)
+                .await
+                .ok_or_log()
+                .flatten(),
+        };
+        if chat.is_some() {
+            metrics::LINKS_PARSED.with_label_values(&[kind]).inc();
+        } else {
+            metrics::LINKS_FAILED.with_label_values(&[kind]).inc();
+        }
+        Ok(chat)
     }
+}
 
+// ---以下为私有方法---
+impl ScanLink {
     async fn parse_chat_msg(
         link_id: i32,
         chat_msg: ChatMessage,
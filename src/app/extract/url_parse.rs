@@ -51,6 +51,14 @@ impl LinkParse {
             LinkParse::MaybeChannel(mc) => mc.source,
         }
     }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LinkParse::ChatMessage(_) => "chat_message",
+            LinkParse::Invite(_) => "invite",
+            LinkParse::MaybeChannel(_) => "maybe_channel",
+        }
+    }
 }
 impl TryFrom<link::Model> for LinkParse {
     type Error = anyhow::Error;
@@ -4,10 +4,9 @@ use anyhow::Result;
 use async_trait::async_trait;
 use chrono::Utc;
 use sea_orm::{ActiveModelTrait, IntoActiveModel, Set};
-use tokio::time::Interval;
 use tracing::warn;
 
-use crate::{app::History, Context, PrintError, Runable};
+use crate::{app::History, supervisor::RestartPolicy, Context, PrintError, Runable};
 
 /// # Process Model
 ///
@@ -30,11 +29,20 @@ impl Runable for Sentence {
     fn name(&self) -> &'static str {
         "周期更新冷历史记录"
     }
+
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::on_failure()
+    }
+
     async fn run(&mut self, ctx: Context) -> Result<()> {
         let mut ticker = tokio::time::interval(Duration::from_secs(300));
 
         loop {
-            let result = tick(&mut ticker, ctx.clone()).await;
+            tokio::select! {
+                _ = ticker.tick() => (),
+                _ = ctx.cancel.cancelled() => return Ok(()),
+            }
+            let result = tick(ctx.clone()).await;
             if result.is_err() {
                 result.ok_or_warn();
                 continue;
@@ -43,9 +51,7 @@ impl Runable for Sentence {
     }
 }
 
-async fn tick(ticker: &mut Interval, ctx: Context) -> Result<()> {
-    ticker.tick().await;
-
+async fn tick(ctx: Context) -> Result<()> {
     // 0. sync chat join status
     ctx.persist.sync_chat_joined(ctx.clone()).await?;
 
@@ -60,6 +66,10 @@ async fn tick(ticker: &mut Interval, ctx: Context) -> Result<()> {
     warn!(oldest.chat_id, "周期更新 >> 开始获取历史");
     let mut task = History::new(oldest.packed()?, 1000000, oldest.last_update);
     task.run(ctx.clone()).await.into_log();
+    if ctx.cancel.is_cancelled() {
+        // 历史未获取完整，不更新时间
+        return Ok(());
+    }
 
     // 3. set update time
     warn!(oldest.chat_id, "周期更新 >> 更新时间");
@@ -0,0 +1,163 @@
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::{
+    context::Context,
+    filter::Filter,
+    persist::Record,
+    supervisor::RestartPolicy,
+    types::chat_event::{ChatEvent, ChatEventKind},
+    update::Updater,
+    PrintError, Runable,
+};
+
+/// 两轮成员数检查的间隔
+const MEMBER_POLL_INTERVAL: Duration = Duration::from_secs(3600);
+/// 成员数变化至少达到此人数，且达到原成员数的[`JUMP_RATIO`]时记录
+const JUMP_MIN: i32 = 20;
+const JUMP_RATIO: f64 = 0.05;
+
+async fn record(context: &Context, event: ChatEvent) -> Result<()> {
+    info!(chat_id = event.chat_id, kind = ?event.kind, "记录聊天事件");
+    context.put(event.to_model()).await?;
+    Ok(())
+}
+
+/// 记录聊天事件：成员变化、标题头像变化、置顶、回应、投票与服务消息，浏览量写入消息
+#[derive(Debug)]
+pub struct EventMirror {
+    filter: Filter,
+}
+impl EventMirror {
+    pub fn with_filter(filter: Filter) -> Self {
+        Self { filter }
+    }
+}
+
+impl Default for EventMirror {
+    /// 只关心服务消息，普通消息交由[`crate::app::LiveMirror`]处理
+    fn default() -> Self {
+        Self::with_filter(Filter::Service)
+    }
+}
+
+#[async_trait]
+impl Updater for EventMirror {
+    fn name(&self) -> &'static str {
+        "聊天事件记录"
+    }
+
+    async fn chat_event(&self, context: Context, event: ChatEvent) -> Result<()> {
+        match event.kind {
+            ChatEventKind::Views => self.views_updated(context, event).await,
+            _ => record(&context, event).await,
+        }
+    }
+
+    /// 频道消息的浏览量频繁推送，只更新消息的`views`列
+    async fn views_updated(&self, context: Context, event: ChatEvent) -> Result<()> {
+        let (Some(msg_id), Some(views)) = (event.msg_id, event.views) else {
+            return Ok(());
+        };
+        if let Some(stored) = context
+            .persist
+            .set_message_views(event.chat_id, msg_id, views)
+            .await?
+        {
+            context.publish(Record::Message(stored)).await;
+        }
+        Ok(())
+    }
+
+    fn filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    fn accept_events(&self) -> bool {
+        true
+    }
+}
+
+/// 成员数变化是否算作跳变
+fn is_jump(previous: i32, count: i32) -> bool {
+    let diff = (count - previous).abs();
+    diff >= JUMP_MIN && diff as f64 >= previous as f64 * JUMP_RATIO
+}
+
+/// 定时检查已加入群组与频道的成员数，跳变时记录[`ChatEventKind::MemberCount`]
+///
+/// 成员数没有推送更新，只能轮询。启动后的第一轮只记录基准。
+#[derive(Debug, Default)]
+pub struct MemberCountWatch {
+    counts: HashMap<i64, i32>,
+}
+
+impl MemberCountWatch {
+    async fn check(&mut self, ctx: &Context, chat_id: i64, count: i32) -> Result<()> {
+        let Some(previous) = self.counts.insert(chat_id, count) else {
+            return Ok(());
+        };
+        if !is_jump(previous, count) {
+            return Ok(());
+        }
+        warn!(chat_id, previous, count, "成员数跳变");
+        let data = json!({ "previous": previous, "count": count });
+        record(
+            ctx,
+            ChatEvent::new(chat_id, ChatEventKind::MemberCount, data),
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl Runable for MemberCountWatch {
+    fn name(&self) -> &'static str {
+        "成员数检查"
+    }
+
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::on_failure()
+    }
+
+    async fn run(&mut self, ctx: Context) -> Result<()> {
+        loop {
+            for chat in ctx.persist.find_joined_groups().await? {
+                if ctx.cancel.is_cancelled() {
+                    return Ok(());
+                }
+                let Some(packed) = chat.packed().ok_or_warn() else {
+                    continue;
+                };
+                if let Some(count) = ctx.member_count(packed).await.ok_or_warn().flatten() {
+                    self.check(&ctx, chat.chat_id, count).await?;
+                }
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(MEMBER_POLL_INTERVAL) => (),
+                _ = ctx.cancel.cancelled() => return Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_jump;
+
+    #[test]
+    fn jump_needs_both_absolute_and_relative_change() {
+        assert!(is_jump(100, 130));
+        assert!(is_jump(130, 100));
+        // 相对变化足够但人数太少
+        assert!(!is_jump(10, 25));
+        // 人数足够但相对变化太小
+        assert!(!is_jump(10000, 10100));
+        assert!(is_jump(10000, 10500));
+        assert!(!is_jump(100, 100));
+    }
+}
@@ -0,0 +1,376 @@
+//! 转发到审核频道
+//!
+//! 命中规则的消息转发到私有审核频道，或附加来源信息后重新发送。同一相册的消息按`grouped_id`
+//! 收齐后一并处理，任一消息命中即整个相册发送。发送由[`ForwardSender`]在后台依次进行，
+//! 每次发送前等待[`IntervalSet::forward_msg`]。
+//!
+//! [`IntervalSet::forward_msg`]: crate::context::IntervalSet
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use grammers_client::{
+    types::{InputMedia, Message as RawMessage, PackedChat},
+    InputMessage,
+};
+use sea_orm::EntityTrait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, Mutex, OnceCell};
+use tracing::{info, warn};
+
+use crate::{
+    chat,
+    context::{retry_on_flood, Context},
+    filter::Filter,
+    supervisor::RestartPolicy,
+    types::MessageExt,
+    update::Updater,
+    PrintError, Runable,
+};
+
+/// 收到相册的第一条消息后等待其余消息的时间
+const ALBUM_WAIT: Duration = Duration::from_secs(2);
+/// 等待发送的批次上限，满时解析任务等待
+const SEND_QUEUE: usize = 256;
+/// 媒体说明的长度上限，按UTF-16计
+const CAPTION_LIMIT: usize = 1024;
+/// 文本消息的长度上限，按UTF-16计
+const TEXT_LIMIT: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ForwardMode {
+    /// 原样转发，保留转发来源
+    #[default]
+    Forward,
+    /// 附加来源信息后重新发送
+    Repost,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForwardRule {
+    pub name: String,
+    pub filter: Filter,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForwardConfig {
+    /// 审核频道的chat_id，须已加入并存储在chat表中
+    pub channel: i64,
+    #[serde(default)]
+    pub mode: ForwardMode,
+    pub rules: Vec<ForwardRule>,
+}
+
+/// 等待收齐的相册
+#[derive(Debug, Default)]
+struct Album {
+    messages: Vec<RawMessage>,
+    rule: Option<String>,
+}
+
+/// 命中同一规则、一并发送的消息，多于一条时为同一相册
+#[derive(Debug)]
+struct Batch {
+    rule: String,
+    messages: Vec<RawMessage>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    config: ForwardConfig,
+    channel: OnceCell<PackedChat>,
+    albums: Mutex<HashMap<(i64, i64), Album>>,
+    queue: mpsc::Sender<Batch>,
+}
+
+#[derive(Debug)]
+pub struct ForwardMirror {
+    inner: Arc<Inner>,
+    filter: Filter,
+}
+
+impl ForwardMirror {
+    /// 返回的[`ForwardSender`]需作为后台任务运行
+    pub fn new(config: ForwardConfig) -> (Self, ForwardSender) {
+        // 不处理审核频道自身的消息
+        let filter = Filter::Incoming.and(Filter::chat_id(config.channel).not());
+        let (queue, rx) = mpsc::channel(SEND_QUEUE);
+        let inner = Arc::new(Inner {
+            config,
+            channel: OnceCell::new(),
+            albums: Mutex::new(HashMap::new()),
+            queue,
+        });
+        let sender = ForwardSender {
+            inner: inner.clone(),
+            rx,
+        };
+        (Self { inner, filter }, sender)
+    }
+}
+
+/// 依次发送[`ForwardMirror`]排队的消息
+pub struct ForwardSender {
+    inner: Arc<Inner>,
+    rx: mpsc::Receiver<Batch>,
+}
+
+#[async_trait]
+impl Runable for ForwardSender {
+    fn name(&self) -> &'static str {
+        "审核频道发送"
+    }
+
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::on_failure()
+    }
+
+    async fn run(&mut self, ctx: Context) -> Result<()> {
+        loop {
+            let batch = tokio::select! {
+                batch = self.rx.recv() => batch,
+                _ = ctx.cancel.cancelled() => {
+                    let remain = self.rx.len();
+                    if remain > 0 {
+                        warn!(remain, "收到退出信号，放弃未发送的消息");
+                    }
+                    return Ok(());
+                }
+            };
+            let Some(batch) = batch else {
+                return Ok(());
+            };
+            let Some(first) = batch.messages.first() else {
+                continue;
+            };
+            let (chat_id, msg_id) = (first.chat().id(), first.id());
+            self.inner
+                .send(&ctx, &batch.rule, &batch.messages)
+                .await
+                .map_err(|e| anyhow!("转发失败 chat_id={chat_id} msg_id={msg_id} >> {e}"))
+                .ok_or_warn();
+        }
+    }
+}
+
+impl Inner {
+    fn matched_rule(&self, msg: &RawMessage) -> Option<&str> {
+        self.config
+            .rules
+            .iter()
+            .find(|rule| rule.filter.matches(msg))
+            .map(|rule| rule.name.as_str())
+    }
+
+    async fn channel(&self, ctx: &Context) -> Result<PackedChat> {
+        let channel = self.config.channel;
+        self.channel
+            .get_or_try_init(|| async {
+                match chat::Entity::find_by_id(channel)
+                    .one(&ctx.persist.db)
+                    .await?
+                {
+                    Some(model) => model.packed(),
+                    None => bail!("审核频道{channel}不在chat表中"),
+                }
+            })
+            .await
+            .copied()
+    }
+
+    /// 来源信息
+    fn header(rule: &str, msg: &RawMessage) -> String {
+        let chat = msg.chat();
+        let mut ret = format!("规则: {rule}\n来源: {} ({})", chat.name(), chat.id());
+        if let Some(username) = chat.username() {
+            ret += &format!("\n链接: https://t.me/{username}/{}", msg.id());
+        }
+        if let Some(sender) = msg.sender() {
+            ret += &format!("\n发送者: {} ({})", sender.name(), sender.id());
+        }
+        ret
+    }
+
+    /// `messages`来自同一聊天，多于一条时为同一相册
+    async fn send(&self, ctx: &Context, rule: &str, messages: &[RawMessage]) -> Result<()> {
+        let Some(first) = messages.first() else {
+            return Ok(());
+        };
+        let channel = self.channel(ctx).await?;
+        let chat = first.chat();
+        ctx.interval.forward_msg.tick().await;
+        info!(
+            rule,
+            chat_id = chat.id(),
+            msg_id = first.id(),
+            count = messages.len(),
+            mode = ?self.config.mode,
+            "转发到审核频道"
+        );
+
+        match self.config.mode {
+            ForwardMode::Forward => {
+                let ids: Vec<i32> = messages.iter().map(|m| m.id()).collect();
+                let header = truncate(&Self::header(rule, first), TEXT_LIMIT);
+                retry_on_flood("send_message", || {
+                    ctx.client
+                        .send_message(channel, InputMessage::text(&header))
+                })
+                .await?;
+                retry_on_flood("forward_messages", || {
+                    ctx.client.forward_messages(channel, &ids, chat.pack())
+                })
+                .await?;
+            }
+            ForwardMode::Repost => {
+                let caption = |msg: &RawMessage, limit| {
+                    truncate(
+                        &format!("{}\n\n{}", Self::header(rule, msg), msg.text()),
+                        limit,
+                    )
+                };
+                let media: Vec<_> = messages.iter().filter_map(|m| m.media()).collect();
+                if messages.len() > 1 && media.len() == messages.len() {
+                    // 说明文字放在相册的第一项
+                    let texts: Vec<String> = messages
+                        .iter()
+                        .enumerate()
+                        .map(|(i, msg)| {
+                            if i == 0 {
+                                caption(msg, CAPTION_LIMIT)
+                            } else {
+                                truncate(msg.text(), CAPTION_LIMIT)
+                            }
+                        })
+                        .collect();
+                    let album = || {
+                        texts
+                            .iter()
+                            .zip(media.iter())
+                            .map(|(text, media)| InputMedia::caption(text).copy_media(media))
+                            .collect()
+                    };
+                    retry_on_flood("send_album", || ctx.client.send_album(channel, album()))
+                        .await?;
+                } else {
+                    for msg in messages {
+                        let media = msg.media();
+                        let limit = if media.is_some() {
+                            CAPTION_LIMIT
+                        } else {
+                            TEXT_LIMIT
+                        };
+                        let text = caption(msg, limit);
+                        let input = || {
+                            let input = InputMessage::text(&text);
+                            match &media {
+                                Some(media) => input.copy_media(media),
+                                None => input,
+                            }
+                        };
+                        retry_on_flood("send_message", || {
+                            ctx.client.send_message(channel, input())
+                        })
+                        .await?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn enqueue(&self, rule: String, messages: Vec<RawMessage>) -> Result<()> {
+        self.queue
+            .send(Batch { rule, messages })
+            .await
+            .map_err(|_| anyhow!("审核频道发送任务已退出"))
+    }
+
+    /// 等待相册收齐后加入发送队列
+    async fn flush_album(self: Arc<Self>, ctx: Context, key: (i64, i64)) {
+        tokio::select! {
+            _ = tokio::time::sleep(ALBUM_WAIT) => (),
+            _ = ctx.cancel.cancelled() => return,
+        }
+        let Some(mut album) = self.albums.lock().await.remove(&key) else {
+            return;
+        };
+        let Some(rule) = album.rule.take() else {
+            return;
+        };
+        album.messages.sort_by_key(|m| m.id());
+        self.enqueue(rule, album.messages)
+            .await
+            .map_err(|e| anyhow!("相册转发失败 chat_id={} grouped_id={} >> {e}", key.0, key.1))
+            .ok_or_warn();
+    }
+}
+
+#[async_trait]
+impl Updater for ForwardMirror {
+    fn name(&self) -> &'static str {
+        "审核频道转发"
+    }
+
+    async fn message_recv(&self, context: Context, msg: MessageExt) -> Result<()> {
+        let msg = msg.inner;
+        let rule = self.inner.matched_rule(&msg).map(|s| s.to_string());
+        let Some(grouped_id) = msg.raw.grouped_id else {
+            if let Some(rule) = rule {
+                self.inner.enqueue(rule, vec![msg]).await?;
+            }
+            return Ok(());
+        };
+
+        let key = (msg.chat().id(), grouped_id);
+        let mut albums = self.inner.albums.lock().await;
+        let first = !albums.contains_key(&key);
+        let album = albums.entry(key).or_default();
+        album.messages.push(msg);
+        if album.rule.is_none() {
+            album.rule = rule;
+        }
+        drop(albums);
+        if first {
+            tokio::spawn(self.inner.clone().flush_album(context, key));
+        }
+        Ok(())
+    }
+
+    fn filter(&self) -> &Filter {
+        &self.filter
+    }
+}
+
+/// 超过`limit`个UTF-16单元时截断，末尾加省略号
+fn truncate(text: &str, limit: usize) -> String {
+    if text.encode_utf16().count() <= limit {
+        return text.to_string();
+    }
+    let mut len = 0;
+    let mut ret: String = text
+        .chars()
+        .take_while(|c| {
+            len += c.len_utf16();
+            len < limit
+        })
+        .collect();
+    ret.push('…');
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_counts_utf16_units() {
+        assert_eq!(truncate("短文本", 10), "短文本");
+        assert_eq!(truncate("abcdef", 4), "abc…");
+        // 表情占两个UTF-16单元
+        let cut = truncate("😀😀😀", 4);
+        assert_eq!(cut, "😀…");
+        assert!(cut.encode_utf16().count() <= 4);
+    }
+}
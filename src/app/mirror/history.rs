@@ -2,14 +2,17 @@ use anyhow::Result;
 use async_trait::async_trait;
 use chrono::{NaiveDateTime, Utc};
 use grammers_client::types::PackedChat;
+use sea_orm::TryIntoModel;
 use tracing::{info, warn};
 
-use crate::{message, Context, PrintError, Runable, Source};
+use crate::{app::extract::forward, message, metrics, user, Context, PrintError, Runable, Source};
 
 pub struct History {
     packed_chat: PackedChat,
     limit: usize,
     since: NaiveDateTime,
+    /// 获取到不大于此编号的消息即停止
+    min_id: Option<i32>,
 }
 impl History {
     pub fn new(packed_chat: PackedChat, limit: usize, since: NaiveDateTime) -> Self {
@@ -17,6 +20,17 @@ impl History {
             packed_chat,
             limit,
             since,
+            min_id: None,
+        }
+    }
+
+    /// 从最新消息向前补齐，直到已存储的`min_id`
+    pub fn after(packed_chat: PackedChat, min_id: Option<i32>, limit: usize) -> Self {
+        Self {
+            packed_chat,
+            limit,
+            since: Utc::now().naive_utc(),
+            min_id,
         }
     }
 }
@@ -45,12 +59,30 @@ impl Runable for History {
         warn!(chat_id, limit, delta_time, "获取聊天记录-开始");
 
         while let Some(Some(msg)) = history.next().await.ok_or_warn() {
+            if ctx.cancel.is_cancelled() {
+                warn!(chat_id, count, "收到退出信号，停止获取聊天记录");
+                break;
+            }
+            if self.min_id.is_some_and(|min_id| msg.id() <= min_id) {
+                info!(chat_id, count, "已追上存储的消息");
+                break;
+            }
             ctx.interval.find_msg.tick().await;
             count += 1;
             info!(chat_id, count, limit, delta_time, "获取聊天记录");
-            ctx.persist
-                .put_message(message::ActiveModel::from_inner_msg(&msg, source))
+            let (_, inserted) = ctx
+                .put(message::ActiveModel::from_inner_msg(&msg, source)?.try_into_model()?)
                 .await?;
+            // 重新同步已存储的消息时不重复计数聊天关系
+            if inserted {
+                forward::track_message(&ctx, &msg).await?;
+            }
+            if let Some((sender, min)) = user::ActiveModel::from_sender(&msg) {
+                ctx.put_sender(sender, min).await?;
+            }
+            metrics::MESSAGES_MIRRORED
+                .with_label_values(&[&chat_id.to_string()])
+                .inc();
         }
 
         if count <= limit {
@@ -0,0 +1,274 @@
+//! 导入Telegram Desktop导出的`result.json`
+//!
+//! 支持单个聊天的导出与整个账号的导出（`chats.list`）。导出文件中的消息不含原始TL对象，
+//! `raw`列保存导出文件中的消息对象，规范化列在导入时直接填入。
+
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use grammers_client::{
+    grammers_tl_types as tl,
+    types::{chat::PackedType, PackedChat},
+};
+use sea_orm::Set;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use tracing::{info, warn};
+
+use crate::{
+    metrics,
+    persist::Database,
+    types::{chat, link, message, unix_time, Source},
+};
+
+/// 每批写入的消息数
+const BATCH: usize = 1000;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImportStats {
+    pub chats: usize,
+    /// 新写入的消息
+    pub messages: u64,
+    /// 已存在或无法解析而跳过的消息
+    pub skipped: u64,
+    /// 新写入的链接
+    pub links: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportChat {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(rename = "type")]
+    ty: String,
+    id: i64,
+    #[serde(default)]
+    messages: Vec<JsonValue>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportMessage {
+    id: i32,
+    #[serde(rename = "type")]
+    ty: String,
+    date_unixtime: Option<String>,
+    edited_unixtime: Option<String>,
+    from_id: Option<String>,
+    reply_to_message_id: Option<i32>,
+    author: Option<String>,
+    media_type: Option<String>,
+    photo: Option<String>,
+    #[serde(default)]
+    text_entities: Vec<TextEntity>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TextEntity {
+    #[serde(rename = "type")]
+    ty: String,
+    text: String,
+    href: Option<String>,
+}
+
+impl ExportChat {
+    fn packed(&self) -> Option<PackedChat> {
+        let ty = match self.ty.as_str() {
+            "public_supergroup" | "private_supergroup" => PackedType::Megagroup,
+            "public_channel" | "private_channel" => PackedType::Broadcast,
+            "private_group" => PackedType::Chat,
+            "personal_chat" => PackedType::User,
+            "bot_chat" => PackedType::Bot,
+            _ => return None,
+        };
+        // 导出文件不含access_hash，无法直接用于请求
+        Some(PackedChat {
+            ty,
+            id: self.id,
+            access_hash: None,
+        })
+    }
+}
+
+impl ExportMessage {
+    fn text(&self) -> String {
+        self.text_entities.iter().map(|e| e.text.as_str()).collect()
+    }
+
+    /// 将导出文件中的超链接转换为TL实体，偏移按UTF-16计算
+    fn entities(&self) -> Vec<tl::enums::MessageEntity> {
+        let mut offset = 0;
+        let mut ret = Vec::new();
+        for e in &self.text_entities {
+            let length = e.text.encode_utf16().count() as i32;
+            if let ("text_link", Some(href)) = (e.ty.as_str(), &e.href) {
+                ret.push(tl::enums::MessageEntity::TextUrl(
+                    tl::types::MessageEntityTextUrl {
+                        offset,
+                        length,
+                        url: href.clone(),
+                    },
+                ));
+            }
+            offset += length;
+        }
+        ret
+    }
+
+    fn links(&self) -> Vec<link::Link> {
+        link::Link::from_entities(&self.text(), &self.entities())
+    }
+
+    fn to_model(&self, chat_id: i64, raw: JsonValue, source: Source) -> message::ActiveModel {
+        let timestamp = |s: &Option<String>| {
+            s.as_deref()
+                .and_then(|s| s.parse::<i32>().ok())
+                .map(unix_time)
+        };
+        message::ActiveModel {
+            chat_id: Set(chat_id),
+            msg_id: Set(self.id),
+            raw: Set(raw),
+            source: Set(source.ty),
+            source_id: Set(source.id),
+            source_chat_id: Set(source.chat_id),
+            text: Set(Some(self.text())),
+            date: Set(timestamp(&self.date_unixtime)),
+            edit_date: Set(timestamp(&self.edited_unixtime)),
+            sender_id: Set(self.from_id.as_deref().and_then(export_peer_id)),
+            fwd_from_chat: Set(None),
+            fwd_from_msg: Set(None),
+            reply_to: Set(self.reply_to_message_id),
+            grouped_id: Set(None),
+            views: Set(None),
+            forwards: Set(None),
+            post_author: Set(self.author.clone()),
+            media_type: Set(self
+                .media_type
+                .clone()
+                .or_else(|| self.photo.as_ref().map(|_| "photo".to_string()))),
+        }
+    }
+}
+
+/// 导出文件中的`user123`、`channel123`
+fn export_peer_id(s: &str) -> Option<i64> {
+    s.trim_start_matches(|c: char| c.is_ascii_alphabetic())
+        .parse()
+        .ok()
+}
+
+pub async fn import_desktop(db: &Database, path: impl AsRef<Path>) -> Result<ImportStats> {
+    let mut root: JsonValue =
+        serde_json::from_reader(std::io::BufReader::new(std::fs::File::open(path)?))?;
+    // 完整导出含多个聊天，单个聊天的导出即为聊天本身
+    let chats = match root.pointer_mut("/chats/list").map(JsonValue::take) {
+        Some(JsonValue::Array(list)) => list,
+        _ => vec![root],
+    };
+
+    let mut stats = ImportStats::default();
+    for chat in chats {
+        let chat: ExportChat = serde_json::from_value(chat)?;
+        import_chat(db, chat, &mut stats).await?;
+    }
+    Ok(stats)
+}
+
+async fn import_chat(db: &Database, chat: ExportChat, stats: &mut ImportStats) -> Result<()> {
+    let Some(packed) = chat.packed() else {
+        warn!(chat_id = chat.id, ty = chat.ty, "跳过不支持的聊天类型");
+        return Ok(());
+    };
+    let chat_id = chat.id;
+    let name = chat.name.clone().unwrap_or_default();
+    let source = Source::from_import(chat_id);
+    info!(chat_id, name, count = chat.messages.len(), "导入聊天");
+
+    let mut last_date = None;
+    let mut batch = Vec::with_capacity(BATCH);
+    for raw in chat.messages {
+        let msg = match serde_json::from_value::<ExportMessage>(raw.clone()) {
+            Ok(msg) if msg.ty == "message" => msg,
+            Ok(_) => {
+                stats.skipped += 1;
+                continue;
+            }
+            Err(e) => {
+                warn!(chat_id, "无法解析导出的消息 >> {e}");
+                stats.skipped += 1;
+                continue;
+            }
+        };
+
+        let link_source = Source::from_message(chat_id, msg.id);
+        for link in msg.links() {
+            let (_, created) = db.put_link_checked(link.to_model(&link_source)).await?;
+            if created {
+                metrics::LINKS_DISCOVERED.inc();
+                stats.links += 1;
+            }
+        }
+
+        let model = msg.to_model(chat_id, raw, source);
+        if let Set(Some(date)) = model.date {
+            last_date = last_date.max(Some(date));
+        }
+        batch.push(model);
+        if batch.len() >= BATCH {
+            let total = batch.len() as u64;
+            let inserted = db.put_messages(std::mem::take(&mut batch)).await?;
+            stats.messages += inserted;
+            stats.skipped += total - inserted;
+        }
+    }
+    let total = batch.len() as u64;
+    let inserted = db.put_messages(batch).await?;
+    stats.messages += inserted;
+    stats.skipped += total - inserted;
+
+    db.put_chat(chat::ActiveModel {
+        chat_id: Set(chat_id),
+        ty: Set(match packed.ty {
+            PackedType::Megagroup | PackedType::Chat => chat::ChatType::Group,
+            PackedType::Broadcast | PackedType::Gigagroup => chat::ChatType::Channel,
+            PackedType::User | PackedType::Bot => chat::ChatType::User,
+        }),
+        usernames: Set(Vec::new()),
+        name: Set(name),
+        packed: Set(packed.to_hex()),
+        source: Set(source.ty),
+        source_id: Set(source.id),
+        source_chat_id: Set(source.chat_id),
+        joined: Set(false),
+        last_update: Set(last_date.unwrap_or_else(|| Utc::now().naive_utc())),
+    })
+    .await
+    .map_err(|e| anyhow!("写入聊天失败 chat_id={chat_id} >> {e}"))?;
+    stats.chats += 1;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_use_utf16_offsets_and_skip_bare_urls() {
+        let msg: ExportMessage = serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "type": "message",
+            "text_entities": [
+                {"type": "plain", "text": "频道😀 "},
+                {"type": "text_link", "text": "入口", "href": "https://t.me/a"},
+                {"type": "plain", "text": " "},
+                {"type": "link", "text": "https://t.me/b"},
+            ],
+        }))
+        .unwrap();
+        let links = msg.links();
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].link, "https://t.me/a");
+        assert_eq!(links[0].desc, "入口");
+    }
+}
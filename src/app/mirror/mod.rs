@@ -1,3 +1,6 @@
+pub mod event;
+pub mod forward;
 pub mod history;
+pub mod import;
 pub mod update;
 pub mod eliminate;
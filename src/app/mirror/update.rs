@@ -1,56 +1,104 @@
+use std::sync::Arc;
+
 use anyhow::Result;
 use async_trait::async_trait;
-use grammers_client::types::Message as RawMessage;
+use sea_orm::TryIntoModel;
 use tracing::info;
 
 use crate::{
+    app::{
+        alert::{AlertDelivery, AlertRule, Alerter},
+        extract::forward,
+    },
     context::Context,
-    types::{message, MessageExt, Source},
+    filter::Filter,
+    metrics,
+    types::{message, user, MessageExt, Source},
     update::Updater,
 };
 
-#[derive(Debug, Default)]
-pub struct LiveMirror;
+#[derive(Debug)]
+pub struct LiveMirror {
+    filter: Filter,
+    /// 新消息的告警，编辑的消息不再告警
+    alerter: Option<Arc<Alerter>>,
+}
+impl LiveMirror {
+    pub fn with_filter(filter: Filter) -> Self {
+        Self {
+            filter,
+            alerter: None,
+        }
+    }
 
-#[async_trait]
-impl Updater for LiveMirror {
-    fn name(&self) -> &'static str {
-        "增量消息镜像"
+    pub fn with_alerts(mut self, rules: Vec<AlertRule>) -> Self {
+        let alerter = Alerter::new(rules);
+        self.alerter = (!alerter.is_empty()).then(|| Arc::new(alerter));
+        self
     }
-    async fn message_recv(&mut self, context: Context, msg: MessageExt) -> Result<()> {
+
+    /// 配置了告警规则时返回投递任务
+    pub fn alert_delivery(&self) -> Option<AlertDelivery> {
+        self.alerter.as_ref().map(|alerter| alerter.delivery())
+    }
+
+    async fn mirror(&self, context: &Context, msg: &MessageExt) -> Result<()> {
         let chat = msg.inner.chat();
         info!(chat_id = chat.id(), "接收更新");
         let source = Source::from_chat(chat.id());
-        context
-            .persist
-            .put_message(message::ActiveModel::from_inner_msg(&msg.inner, source))
+        let (_, inserted) = context
+            .put(message::ActiveModel::from_inner_msg(&msg.inner, source)?.try_into_model()?)
             .await?;
+        // 编辑后的消息也经过此处，聊天关系只在首次写入时计数
+        if inserted {
+            forward::track_message(context, &msg.inner).await?;
+        }
+        if let Some((sender, min)) = user::ActiveModel::from_sender(&msg.inner) {
+            context.put_sender(sender, min).await?;
+        }
+        metrics::MESSAGES_MIRRORED
+            .with_label_values(&[&chat.id().to_string()])
+            .inc();
         msg.inner.mark_as_read().await.ok();
         Ok(())
     }
+}
+impl Default for LiveMirror {
+    /// 接收非本账号发出的、非机器人私聊的消息
+    fn default() -> Self {
+        Self::with_filter(Filter::Incoming.and(Filter::BotChat.not()))
+    }
+}
+
+#[async_trait]
+impl Updater for LiveMirror {
+    fn name(&self) -> &'static str {
+        "增量消息镜像"
+    }
+    async fn message_recv(&self, context: Context, msg: MessageExt) -> Result<()> {
+        self.mirror(&context, &msg).await?;
+        if let Some(alerter) = &self.alerter {
+            alerter.check(&context, &msg.inner).await?;
+        }
+        Ok(())
+    }
 
     /// Occurs when a message is updated.
-    async fn message_edited(&mut self, context: Context, msg: MessageExt) -> Result<()> {
-        self.message_recv(context.clone(), msg).await?;
+    async fn message_edited(&self, context: Context, msg: MessageExt) -> Result<()> {
+        self.mirror(&context, &msg).await?;
         Ok(())
     }
 
-    fn raw_msg_filter(&self, raw_msg: &RawMessage) -> bool {
-        let mut flag = true;
-
-        if self.filter_incoming() {
-            raw_msg.outgoing().then(|| flag = false);
-        }
+    fn resync_on_lag(&self) -> bool {
+        true
+    }
 
-        match raw_msg.chat() {
-            grammers_client::types::Chat::User(u) => {
-                if u.is_bot() {
-                    flag = false;
-                }
-            }
-            _ => (),
-        }
+    /// 数据库写入较慢，按聊天并行处理
+    fn concurrency(&self) -> usize {
+        8
+    }
 
-        flag
+    fn filter(&self) -> &Filter {
+        &self.filter
     }
 }
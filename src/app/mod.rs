@@ -1,11 +1,17 @@
+pub mod alert;
 pub mod extract;
 pub mod mirror;
 pub mod search;
 
 use std::future::Future;
 
-pub use extract::ScanLink;
-pub use mirror::{history::History, update::LiveMirror};
+pub use extract::{ScanForward, ScanLink};
+pub use mirror::{
+    event::{EventMirror, MemberCountWatch},
+    forward::ForwardMirror,
+    history::History,
+    update::LiveMirror,
+};
 pub use search::SearchLink;
 
 use crate::Context;
@@ -1,139 +1,304 @@
-use std::{sync::Arc, time::Duration};
+use std::{fmt::Debug, str::FromStr, sync::Arc, time::Duration};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
+use cron::Schedule;
 use grammers_client::{session::PackedType, types::PackedChat};
+use sea_orm::TryIntoModel;
+use serde::{Deserialize, Serialize};
 use tokio::{sync::Mutex, time::Instant};
 use tracing::info;
 
-use crate::{message, Context, MessageExt, Source, Updater};
+use super::{inline::InlineBot, jisou::Jisou, peer::BotPeer, soso::Soso, SearchRun};
+use crate::{filter::Filter, link, message, Context, MessageExt, Source, Updater};
 
-#[derive(Debug, Clone, Copy)]
-pub struct GenericEngine {
-    pub name: &'static str,
-    pub chat: PackedChat,
+/// 点击翻页按钮前的等待时间
+const CLICK_DELAY: Duration = Duration::from_secs(10);
+
+/// 翻页结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Page {
+    /// 已请求下一页
+    Next,
+    /// 没有更多结果
+    End,
 }
 
-impl GenericEngine {
-    pub const SOSO: GenericEngine = GenericEngine {
-        name: "SOSO",
-        chat: PackedChat {
-            ty: PackedType::Bot,
-            id: 7048419795,
-            access_hash: Some(7758671014432728719),
-        },
-    };
+/// 搜索机器人：如何发送查询、解析结果、翻页与判断结果结束
+#[async_trait]
+pub trait SearchEngine: Debug + Send + Sync + 'static {
+    /// 记录在`search`表的`bot`列
+    fn name(&self) -> &str;
 
-    pub const JISOU: GenericEngine = GenericEngine {
-        name: "jiso2bot",
-        chat: PackedChat {
-            ty: PackedType::Bot,
-            id: 6213379764,
-            access_hash: Some(7074953819817629361),
-        },
-    };
+    /// 机器人的聊天，结果消息来自此聊天
+    fn chat(&self) -> PackedChat;
+
+    /// 发送查询
+    ///
+    /// 结果以消息形式到达的引擎由[`Scraper`]处理；结果随请求直接返回的引擎（如内联查询）
+    /// 在此逐页写入`run`并完成搜索
+    async fn send_query(&self, ctx: &Context, keyword: &str, run: &Mutex<SearchRun>) -> Result<()>;
+
+    /// 属于此关键词的结果消息
+    fn result_filter(&self, keyword: &str) -> Filter {
+        Filter::Incoming
+            .and(Filter::chat_id(self.chat().id))
+            .and(Filter::word(keyword))
+    }
+
+    /// 结果消息中的链接
+    fn parse_results(&self, msg: &MessageExt) -> Vec<link::Link> {
+        msg.links()
+    }
+
+    /// 请求下一页
+    async fn next_page(&self, _ctx: &Context, _msg: &MessageExt) -> Result<Page> {
+        Ok(Page::End)
+    }
+
+    /// 结果消息无法对应到关键词的引擎返回共享的锁，同一时间只搜索一个关键词
+    fn query_lock(&self) -> Option<&'static Mutex<()>> {
+        None
+    }
+}
+
+/// 配置与命令行中选择的搜索引擎
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum EngineKind {
+    #[default]
+    Soso,
+    Jisou,
+    /// 内联查询机器人的用户名
+    Inline(String),
+}
 
-    pub async fn new(username: &'static str, ctx: Context) -> Result<Option<Self>> {
-        if let Some(chat) = ctx.resolve_username(username).await? {
-            Ok(Some(Self {
-                name: username,
-                chat: chat.pack(),
-            }))
-        } else {
-            Ok(None)
+/// 内置搜索引擎的机器人用户名
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchBots {
+    pub soso: String,
+    pub jisou: String,
+}
+
+impl Default for SearchBots {
+    fn default() -> Self {
+        Self {
+            soso: "soso".to_string(),
+            jisou: "jiso2bot".to_string(),
         }
     }
+}
 
-    pub fn start_search(
-        &self,
-        keyword: &'static str,
-        source: Source,
-        time_sync: Arc<Mutex<Instant>>,
-    ) -> impl Updater {
-        Scraper::new(self.clone(), keyword, source, time_sync)
+/// 配置文件中的搜索
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchConfig {
+    pub keyword: String,
+    #[serde(default)]
+    pub engine: EngineKind,
+    /// 重复搜索的cron表达式，含秒字段，如`0 0 */6 * * *`；缺省时只在启动时搜索一次
+    #[serde(default)]
+    pub schedule: Option<String>,
+}
+
+/// 解析cron表达式
+pub fn parse_schedule(expr: &str) -> Result<Schedule> {
+    Schedule::from_str(expr).map_err(|e| anyhow!("无效的cron表达式{expr} >> {e}"))
+}
+
+impl FromStr for EngineKind {
+    type Err = anyhow::Error;
+
+    /// `soso`、`jisou`或`inline:机器人用户名`
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "soso" => Ok(Self::Soso),
+            "jisou" => Ok(Self::Jisou),
+            _ => match s.split_once(':') {
+                Some((prefix, username))
+                    if prefix.eq_ignore_ascii_case("inline") && !username.is_empty() =>
+                {
+                    Ok(Self::Inline(username.trim_start_matches('@').to_string()))
+                }
+                _ => bail!("未知的搜索引擎{s}，可选soso、jisou、inline:机器人用户名"),
+            },
+        }
     }
 }
 
+impl EngineKind {
+    /// 按用户名解析机器人，见[`BotPeer::resolve`]
+    pub async fn build(&self, ctx: &Context) -> Result<Arc<dyn SearchEngine>> {
+        let bots = &ctx.config.search_bots;
+        Ok(match self {
+            EngineKind::Soso => Arc::new(Soso::new(BotPeer::resolve(ctx, &bots.soso).await?)),
+            EngineKind::Jisou => Arc::new(Jisou::new(BotPeer::resolve(ctx, &bots.jisou).await?)),
+            EngineKind::Inline(username) => {
+                let peer = BotPeer::resolve(ctx, username).await?;
+                if peer.get().ty != PackedType::Bot {
+                    bail!("{username}不是机器人，无法内联查询");
+                }
+                Arc::new(InlineBot::new(username.clone(), peer))
+            }
+        })
+    }
+}
+
+/// 点击文字包含任一`labels`的按钮翻页，没有此按钮时结果结束
+pub async fn click_next(ctx: &Context, msg: &MessageExt, labels: &[&str]) -> Result<Page> {
+    let Some(button) = msg
+        .callback_buttons()
+        .into_iter()
+        .find(|b| labels.iter().any(|label| b.text.contains(label)))
+    else {
+        return Ok(Page::End);
+    };
+    // 机器人通常不回应按钮，而是直接修改或发送消息，忽略回应超时
+    let _ = msg
+        .click_callback_button(&ctx.client, &button, CLICK_DELAY)
+        .await;
+    Ok(Page::Next)
+}
+
+/// 处理以消息形式到达的结果
 #[derive(Debug)]
 pub struct Scraper {
-    pub keyword: &'static str,
-    pub source: Source,
-    pub engine: GenericEngine,
-    last_update: Arc<Mutex<Instant>>,
+    pub keyword: Arc<str>,
+    pub engine: Arc<dyn SearchEngine>,
+    run: Arc<Mutex<SearchRun>>,
+    filter: Filter,
 }
 
 impl Scraper {
     pub fn new(
-        engine: GenericEngine,
-        keyword: &'static str,
-        source: Source,
-        last_update: Arc<Mutex<Instant>>,
+        engine: Arc<dyn SearchEngine>,
+        keyword: Arc<str>,
+        run: Arc<Mutex<SearchRun>>,
     ) -> Self {
         Scraper {
+            filter: engine.result_filter(&keyword),
             keyword,
-            source,
             engine,
-            last_update,
+            run,
         }
     }
+
+    /// 附加过滤器，须与引擎自身的过滤器同时满足
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = self.filter.and(filter);
+        self
+    }
 }
 
 #[async_trait]
 impl Updater for Scraper {
     fn name(&self) -> &'static str {
-        self.engine.name
-    }
-    async fn message_recv(&mut self, context: Context, msg: MessageExt) -> Result<()> {
-        let msg_id = context
-            .persist
-            .put_message(message::ActiveModel::from_inner_msg(
-                &msg.inner,
-                self.source,
-            ))
-            .await?
-            .msg_id;
-
-        let link_source = Source::from_message(msg_id);
-        for link in msg.links() {
-            info!(desc=link.desc, "接收链接");
-            context
-                .persist
-                .put_link(link.to_model(&link_source))
-                .await?;
-        }
-
-        let buttons = msg.callback_buttons();
-        for btn in buttons {
-            if btn.text.contains("下一页") || btn.text.contains("➡️") {
-                let _ = msg
-                    .click_callback_button(&context.client, &btn, Duration::from_secs(10))
-                    .await;
-                // 搜搜机器人不会有返回值，而是直接修改消息内容，直接忽略
+        "搜索结果解析"
+    }
+    async fn message_recv(&self, context: Context, msg: MessageExt) -> Result<()> {
+        let source = {
+            let mut run = self.run.lock().await;
+            // 尚未发送查询时到达的消息属于其他关键词的搜索
+            if !run.queried {
+                return Ok(());
             }
-        }
-
-        let mut last = self.last_update.lock().await;
-        *last = Instant::now();
+            // 结束后迟到的结果页同样推迟下一个关键词的查询
+            run.last_message = Instant::now();
+            if run.finished {
+                return Ok(());
+            }
+            run.source
+        };
+        let (stored, _) = context
+            .put(message::ActiveModel::from_inner_msg(&msg.inner, source)?.try_into_model()?)
+            .await?;
 
+        let link_source = Source::from_message(stored.chat_id, stored.msg_id);
         msg.inner.mark_as_read().await.ok();
 
+        let page = {
+            let mut run = self.run.lock().await;
+            if run.finished {
+                return Ok(());
+            }
+            run.record_page(&context, self.engine.parse_results(&msg), &link_source)
+                .await?
+        };
+        // 翻页等待期间不占用进度，看门狗仍可检查
+        let page = match page {
+            Page::Next => self.engine.next_page(&context, &msg).await?,
+            Page::End => {
+                info!(
+                    engine = self.engine.name(),
+                    keyword = &*self.keyword,
+                    "此页没有新链接"
+                );
+                Page::End
+            }
+        };
+
+        let mut run = self.run.lock().await;
+        run.last_update = tokio::time::Instant::now();
+        if page == Page::End {
+            run.finish(&context).await?;
+        }
+
         Ok(())
     }
 
-    async fn message_edited(&mut self, client: Context, msg: MessageExt) -> Result<()> {
+    async fn message_edited(&self, client: Context, msg: MessageExt) -> Result<()> {
         self.message_recv(client, msg).await?;
         Ok(())
     }
 
-    fn filter_incoming(&self) -> bool {
-        true
+    fn filter(&self) -> &Filter {
+        &self.filter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn engine_kind_from_str() {
+        assert_eq!("soso".parse::<EngineKind>().unwrap(), EngineKind::Soso);
+        assert_eq!("SOSO".parse::<EngineKind>().unwrap(), EngineKind::Soso);
+        assert_eq!("Jisou".parse::<EngineKind>().unwrap(), EngineKind::Jisou);
+        assert_eq!(
+            "inline:@SearchBot".parse::<EngineKind>().unwrap(),
+            EngineKind::Inline("SearchBot".to_string())
+        );
+        assert_eq!(
+            "inline:vid".parse::<EngineKind>().unwrap(),
+            EngineKind::Inline("vid".to_string())
+        );
+        // 用户名保留大小写
+        assert_eq!(
+            "Inline:VidBot".parse::<EngineKind>().unwrap(),
+            EngineKind::Inline("VidBot".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_schedule_with_seconds() {
+        let schedule = parse_schedule("0 0 */6 * * *").unwrap();
+        let times: Vec<_> = schedule.upcoming(chrono::Utc).take(2).collect();
+        assert_eq!((times[1] - times[0]).num_hours(), 6);
+        assert!(parse_schedule("0 30 9 * * Mon-Fri").is_ok());
     }
 
-    fn filter_chat_id(&self) -> Option<i64> {
-        Some(self.engine.chat.id)
+    #[test]
+    fn parse_schedule_rejects_invalid() {
+        assert!(parse_schedule("").is_err());
+        assert!(parse_schedule("every hour").is_err());
+        assert!(parse_schedule("0 0 25 * * *").is_err());
     }
 
-    fn filter_word(&self) -> Option<String> {
-        Some(format!("{}", self.keyword))
+    #[test]
+    fn engine_kind_rejects_unknown() {
+        assert!("google".parse::<EngineKind>().is_err());
+        assert!("inline:".parse::<EngineKind>().is_err());
+        assert!("inline".parse::<EngineKind>().is_err());
+        assert!("other:bot".parse::<EngineKind>().is_err());
     }
 }
@@ -0,0 +1,109 @@
+//! 内联查询机器人
+//!
+//! 以`@机器人 关键词`的内联查询获取结果，结果随请求返回，按`next_offset`翻页直到为空。
+
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use grammers_client::{grammers_tl_types as tl, types::PackedChat};
+use tokio::sync::Mutex;
+use tracing::info;
+
+use super::{
+    engine::{Page, SearchEngine},
+    peer::BotPeer,
+    SearchRun,
+};
+use crate::{link, Context};
+
+/// 单次查询最多获取的页数
+const MAX_PAGES: usize = 50;
+const PAGE_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug)]
+pub struct InlineBot {
+    pub name: String,
+    pub peer: BotPeer,
+}
+
+impl InlineBot {
+    pub fn new(name: String, peer: BotPeer) -> Self {
+        Self { name, peer }
+    }
+}
+
+/// 单个结果中的链接：结果本身的地址与发送内容中的超链接
+fn result_links(result: tl::enums::BotInlineResult) -> Vec<link::Link> {
+    let (title, url, send_message) = match result {
+        tl::enums::BotInlineResult::Result(r) => (r.title, r.url, r.send_message),
+        tl::enums::BotInlineResult::BotInlineMediaResult(r) => (r.title, None, r.send_message),
+    };
+    let mut ret = Vec::new();
+    if let Some(url) = url {
+        ret.push(link::Link {
+            link: url,
+            desc: title.unwrap_or_default(),
+        });
+    }
+    let (text, entities) = match send_message {
+        tl::enums::BotInlineMessage::Text(m) => (m.message, m.entities),
+        tl::enums::BotInlineMessage::MediaAuto(m) => (m.message, m.entities),
+        _ => return ret,
+    };
+    if let Some(entities) = entities {
+        ret.extend(link::Link::from_entities(&text, &entities));
+    }
+    ret
+}
+
+#[async_trait]
+impl SearchEngine for InlineBot {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn chat(&self) -> PackedChat {
+        self.peer.get()
+    }
+
+    async fn send_query(&self, ctx: &Context, keyword: &str, run: &Mutex<SearchRun>) -> Result<()> {
+        let mut run = run.lock().await;
+        let source = run.source;
+        let mut offset = String::new();
+        for page in 0..MAX_PAGES {
+            let request = |chat: PackedChat| {
+                let request = tl::functions::messages::GetInlineBotResults {
+                    // 构建时已确认是机器人
+                    bot: chat
+                        .try_to_input_user()
+                        .unwrap_or(tl::enums::InputUser::Empty),
+                    peer: tl::enums::InputPeer::PeerSelf,
+                    geo_point: None,
+                    query: keyword.to_string(),
+                    offset: offset.clone(),
+                };
+                async move { ctx.client.invoke(&request).await }
+            };
+            let tl::enums::messages::BotResults::Results(results) =
+                self.peer.call(ctx, request).await?;
+            info!(
+                engine = self.name,
+                keyword,
+                page,
+                count = results.results.len(),
+                "内联查询结果"
+            );
+            let links = results.results.into_iter().flat_map(result_links).collect();
+            if run.record_page(ctx, links, &source).await? == Page::End {
+                break;
+            }
+            match results.next_offset {
+                Some(next) if !next.is_empty() => offset = next,
+                _ => break,
+            }
+            tokio::time::sleep(PAGE_INTERVAL).await;
+        }
+        run.finish(ctx).await
+    }
+}
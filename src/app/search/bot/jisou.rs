@@ -0,0 +1,72 @@
+//! 极搜机器人
+//!
+//! 翻页后发送新的结果消息而非编辑原消息，新消息不一定包含关键词，因此只按机器人聊天过滤，
+//! 并由[`QUERY_LOCK`]保证同一时间只搜索一个关键词，看门狗等机器人安静后才释放。
+//! 没有结果时回复提示文字且不带翻页按钮。
+
+use anyhow::Result;
+use async_trait::async_trait;
+use grammers_client::types::PackedChat;
+use tokio::sync::Mutex;
+
+use super::{
+    engine::{click_next, Page, SearchEngine},
+    peer::BotPeer,
+    SearchRun,
+};
+use crate::{filter::Filter, Context, MessageExt};
+
+const NEXT_LABELS: [&str; 3] = ["下一页", "»", "▶"];
+const NO_RESULT: [&str; 2] = ["没有找到", "暂无结果"];
+
+/// 全部极搜搜索共用
+static QUERY_LOCK: Mutex<()> = Mutex::const_new(());
+
+#[derive(Debug)]
+pub struct Jisou {
+    pub peer: BotPeer,
+}
+
+impl Jisou {
+    pub fn new(peer: BotPeer) -> Self {
+        Self { peer }
+    }
+}
+
+#[async_trait]
+impl SearchEngine for Jisou {
+    fn name(&self) -> &str {
+        "jiso2bot"
+    }
+
+    fn chat(&self) -> PackedChat {
+        self.peer.get()
+    }
+
+    async fn send_query(
+        &self,
+        ctx: &Context,
+        keyword: &str,
+        _run: &Mutex<SearchRun>,
+    ) -> Result<()> {
+        self.peer
+            .call(ctx, |chat| ctx.client.send_message(chat, keyword))
+            .await?;
+        Ok(())
+    }
+
+    fn result_filter(&self, _keyword: &str) -> Filter {
+        Filter::Incoming.and(Filter::chat_id(self.chat().id))
+    }
+
+    async fn next_page(&self, ctx: &Context, msg: &MessageExt) -> Result<Page> {
+        if NO_RESULT.iter().any(|s| msg.text().contains(s)) {
+            return Ok(Page::End);
+        }
+        click_next(ctx, msg, &NEXT_LABELS).await
+    }
+
+    fn query_lock(&self) -> Option<&'static Mutex<()>> {
+        Some(&QUERY_LOCK)
+    }
+}
@@ -2,31 +2,193 @@ use std::{sync::Arc, time::Duration};
 
 use crate::{
     context::Context,
+    link, metrics,
     types::{search, Source},
     App, PrintError,
 };
 
-use engine::GenericEngine;
-use sea_orm::Set;
-use tokio::sync::Mutex;
-use tracing::warn;
+use anyhow::Result;
+use cron::Schedule;
+use engine::{Page, SearchEngine};
+use tokio::{sync::Mutex, time::Instant};
+use tracing::{info, warn};
 
 pub mod engine;
+pub mod inline;
+pub mod jisou;
+pub mod peer;
+pub mod soso;
 pub mod watchdog;
 
 pub const BOT_RESP_TIMEOUT: Duration = std::time::Duration::from_secs(60);
 pub const BOT_RESEND_INTERVAL: Duration = std::time::Duration::from_secs(60);
 
+/// 一次搜索的进度，由结果解析器与看门狗共享，翻页统计记录在`search`表
+#[derive(Debug)]
+pub struct SearchRun {
+    pub search: search::Model,
+    pub source: Source,
+    pub last_update: Instant,
+    /// 最近一次收到机器人的消息，包括结束后迟到的结果页
+    pub last_message: Instant,
+    /// 已发送查询，此前到达的结果消息不属于本次搜索
+    pub queried: bool,
+    /// 结果已全部获取，看门狗不再重发
+    pub finished: bool,
+}
+
+impl SearchRun {
+    /// 新建`search`行，`exhaustive`时翻到最后一页，见[`search::Model::exhaustive`]
+    pub async fn start(
+        ctx: &Context,
+        engine: &dyn SearchEngine,
+        keyword: &str,
+        exhaustive: bool,
+    ) -> Result<Self> {
+        warn!(engine = engine.name(), keyword, exhaustive, "新建搜索");
+        // 主键为0，写入时由数据库分配
+        let search = search::Model {
+            id: 0,
+            bot: engine.name().to_string(),
+            start_time: chrono::Local::now().naive_local(),
+            keyword: keyword.to_string(),
+            pages: 0,
+            results: 0,
+            new_links: 0,
+            known_links: 0,
+            completed: false,
+            end_time: None,
+            exhaustive,
+        };
+        let (search, _) = ctx.put(search).await?;
+        Ok(Self {
+            source: Source::from_search(&search),
+            search,
+            last_update: Instant::now(),
+            last_message: Instant::now(),
+            queried: false,
+            finished: false,
+        })
+    }
+
+    /// 写入一页结果中的链接，此页没有新链接且不要求翻到最后一页时返回[`Page::End`]
+    pub async fn record_page(
+        &mut self,
+        ctx: &Context,
+        links: Vec<link::Link>,
+        source: &Source,
+    ) -> Result<Page> {
+        let total = links.len() as i32;
+        let mut new_links = 0;
+        for link in links {
+            info!(desc=link.desc, "接收链接");
+            let (stored, created) = ctx.put(link.into_model(source)).await?;
+            ctx.persist
+                .put_search_result(self.search.id, stored.id, created)
+                .await?;
+            if created {
+                new_links += 1;
+            }
+        }
+        metrics::LINKS_DISCOVERED.inc_by(new_links as u64);
+
+        self.last_update = Instant::now();
+        self.search.pages += 1;
+        self.search.results += total;
+        self.search.new_links += new_links;
+        self.search.known_links += total - new_links;
+        self.save(ctx).await?;
+        info!(
+            search_id = self.search.id,
+            page = self.search.pages,
+            total,
+            new_links,
+            "结果页"
+        );
+        Ok(if new_links == 0 && !self.search.exhaustive {
+            Page::End
+        } else {
+            Page::Next
+        })
+    }
+
+    /// 标记搜索完成，看门狗随后退出
+    pub async fn finish(&mut self, ctx: &Context) -> Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.search.completed = true;
+        self.search.end_time = Some(chrono::Local::now().naive_local());
+        self.save(ctx).await?;
+        info!(
+            search_id = self.search.id,
+            keyword = self.search.keyword,
+            pages = self.search.pages,
+            new_links = self.search.new_links,
+            "搜索完成"
+        );
+        Ok(())
+    }
+
+    /// 放弃未到最后一页的搜索，`completed`保持为`false`，不参与前后比较
+    pub async fn abandon(&mut self, ctx: &Context) -> Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.search.end_time = Some(chrono::Local::now().naive_local());
+        self.save(ctx).await?;
+        warn!(
+            search_id = self.search.id,
+            keyword = self.search.keyword,
+            pages = self.search.pages,
+            "搜索未到最后一页即中断"
+        );
+        Ok(())
+    }
+
+    async fn save(&mut self, ctx: &Context) -> Result<()> {
+        self.search = ctx.put(self.search.clone()).await?.0;
+        Ok(())
+    }
+}
+
+/// 一个关键词的搜索，设置了`schedule`时按计划重复搜索
+#[derive(Debug)]
+pub struct KeywordSearch {
+    pub engine: Arc<dyn SearchEngine>,
+    pub keyword: Arc<str>,
+    pub schedule: Option<Schedule>,
+}
+
+impl KeywordSearch {
+    pub fn new(engine: Arc<dyn SearchEngine>, keyword: Arc<str>) -> Self {
+        Self {
+            engine,
+            keyword,
+            schedule: None,
+        }
+    }
+
+    pub fn with_schedule(mut self, schedule: Option<Schedule>) -> Self {
+        self.schedule = schedule;
+        self
+    }
+}
+
 #[derive(Debug)]
 pub struct SearchLink {
-    engine: GenericEngine,
-    keywords: Vec<&'static str>,
+    searches: Vec<KeywordSearch>,
 }
 impl SearchLink {
-    pub fn new(engine: GenericEngine, keywords: impl Iterator<Item = &'static str>) -> Self {
+    pub fn new(engine: Arc<dyn SearchEngine>, keywords: impl Iterator<Item = Arc<str>>) -> Self {
+        Self::with_searches(keywords.map(|keyword| KeywordSearch::new(engine.clone(), keyword)))
+    }
+
+    pub fn with_searches(searches: impl Iterator<Item = KeywordSearch>) -> Self {
         Self {
-            engine,
-            keywords: keywords.collect(),
+            searches: searches.collect(),
         }
     }
 }
@@ -38,34 +200,32 @@ impl App for SearchLink {
     async fn ignite(&mut self, ctx: Context) -> Option<()> {
         // 新建计时器
         let bot_resend = Arc::new(Mutex::new(tokio::time::interval(BOT_RESEND_INTERVAL)));
-        for keyword in &self.keywords {
+        for KeywordSearch {
+            engine,
+            keyword,
+            schedule,
+        } in &self.searches
+        {
             // 新建搜索
-            warn!(keyword, "新建搜索");
-            let search = search::ActiveModel {
-                bot: Set(self.engine.name.to_string()),
-                start_time: Set(chrono::Local::now().naive_local()),
-                keyword: Set(keyword.to_string()),
-                ..Default::default()
-            };
-            let search = ctx.persist.put_search(search).await.ok_or_log()?;
-            let source = Source::from_search(&search);
-
-            // 时间同步量
-            let time_sync = Arc::new(Mutex::new(tokio::time::Instant::now()));
-            // 启动WD
+            // 定期重复的搜索需要完整的结果才能比较前后差异
+            let run = SearchRun::start(&ctx, engine.as_ref(), keyword, schedule.is_some())
+                .await
+                .ok_or_log()?;
+            let run = Arc::new(Mutex::new(run));
+            // 先启动更新处理器，再由WD发送查询
+            let mut scraper = engine::Scraper::new(engine.clone(), keyword.clone(), run.clone());
+            if let Some(filter) = ctx.config.search_filter.clone() {
+                scraper = scraper.with_filter(filter);
+            }
+            ctx.add_parser(scraper).await;
             let watchdog = watchdog::Watchdog::new(
-                self.engine,
-                keyword,
-                time_sync.clone(),
+                engine.clone(),
+                keyword.clone(),
+                run.clone(),
                 bot_resend.clone(),
-            );
-            ctx.add_runable(watchdog).await;
-            // 启动更新处理器
-            ctx.add_parser(
-                self.engine
-                    .start_search(&keyword, source, time_sync.clone()),
             )
-            .await;
+            .with_schedule(schedule.clone());
+            ctx.add_runable(watchdog).await;
             tokio::time::sleep(Duration::from_secs(7)).await;
         }
         Some(())
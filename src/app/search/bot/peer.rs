@@ -0,0 +1,98 @@
+//! 按用户名解析搜索机器人
+//!
+//! 解析结果缓存在`chat`表，启动时优先使用缓存。缓存的access_hash被拒绝时重新解析并更新缓存。
+
+use std::{future::Future, sync::RwLock};
+
+use anyhow::{anyhow, bail, Result};
+use grammers_client::{types::PackedChat, InvocationError};
+use tracing::{info, warn};
+
+use crate::{chat, Context, Source};
+
+/// access_hash失效或对应的对象已不存在
+const PEER_INVALID: [&str; 4] = [
+    "PEER_ID_INVALID",
+    "USER_ID_INVALID",
+    "BOT_INVALID",
+    "CHANNEL_INVALID",
+];
+
+fn is_peer_invalid(e: &InvocationError) -> bool {
+    matches!(e, InvocationError::Rpc(e) if PEER_INVALID.contains(&e.name.as_str()))
+}
+
+#[derive(Debug)]
+pub struct BotPeer {
+    pub username: String,
+    chat: RwLock<PackedChat>,
+}
+
+impl BotPeer {
+    /// 优先使用`chat`表中的缓存，没有缓存时调用`resolve_username`
+    pub async fn resolve(ctx: &Context, username: &str) -> Result<Self> {
+        let username = username.trim_start_matches('@');
+        let chat = match ctx.persist.find_chat(Some(username)).await? {
+            Some(model) => model.packed()?,
+            None => Self::fetch(ctx, username).await?,
+        };
+        Ok(Self {
+            username: username.to_string(),
+            chat: RwLock::new(chat),
+        })
+    }
+
+    async fn fetch(ctx: &Context, username: &str) -> Result<PackedChat> {
+        let chat = ctx
+            .resolve_username(username)
+            .await?
+            .ok_or(anyhow!("未找到搜索机器人{username}"))?;
+        info!(username, chat_id = chat.id(), "解析搜索机器人");
+        ctx.persist
+            .refresh_chat(chat::ActiveModel::from_chat(
+                &chat,
+                false,
+                Source::from_manual(),
+            ))
+            .await?;
+        Ok(chat.pack())
+    }
+
+    pub fn get(&self) -> PackedChat {
+        *self.chat.read().expect("搜索机器人锁中毒")
+    }
+
+    /// 重新解析并更新缓存
+    ///
+    /// 用户名已指向其他账号时返回错误：结果过滤器按原账号构建，需重新启动搜索
+    pub async fn refresh(&self, ctx: &Context) -> Result<PackedChat> {
+        warn!(username = self.username, "access_hash被拒绝，重新解析");
+        let chat = Self::fetch(ctx, &self.username).await?;
+        let mut current = self.chat.write().expect("搜索机器人锁中毒");
+        if current.id != chat.id {
+            bail!(
+                "搜索机器人{}已指向其他账号 old={} new={}，缓存已更新，请重新启动搜索",
+                self.username,
+                current.id,
+                chat.id
+            );
+        }
+        *current = chat;
+        Ok(chat)
+    }
+
+    /// 以机器人为对象调用，access_hash被拒绝时重新解析后重试一次
+    pub async fn call<T, F, Fut>(&self, ctx: &Context, f: F) -> Result<T>
+    where
+        F: Fn(PackedChat) -> Fut,
+        Fut: Future<Output = Result<T, InvocationError>>,
+    {
+        match f(self.get()).await {
+            Err(e) if is_peer_invalid(&e) => {
+                let chat = self.refresh(ctx).await?;
+                Ok(f(chat).await?)
+            }
+            ret => Ok(ret?),
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! 搜搜机器人
+//!
+//! 发送关键词后回复一条结果消息，翻页时原地编辑此消息，没有下一页按钮即结果结束。
+
+use anyhow::Result;
+use async_trait::async_trait;
+use grammers_client::types::PackedChat;
+use tokio::sync::Mutex;
+
+use super::{
+    engine::{click_next, Page, SearchEngine},
+    peer::BotPeer,
+    SearchRun,
+};
+use crate::{Context, MessageExt};
+
+const NEXT_LABELS: [&str; 2] = ["下一页", "➡️"];
+
+#[derive(Debug)]
+pub struct Soso {
+    pub peer: BotPeer,
+}
+
+impl Soso {
+    pub fn new(peer: BotPeer) -> Self {
+        Self { peer }
+    }
+}
+
+#[async_trait]
+impl SearchEngine for Soso {
+    fn name(&self) -> &str {
+        "SOSO"
+    }
+
+    fn chat(&self) -> PackedChat {
+        self.peer.get()
+    }
+
+    async fn send_query(
+        &self,
+        ctx: &Context,
+        keyword: &str,
+        _run: &Mutex<SearchRun>,
+    ) -> Result<()> {
+        self.peer
+            .call(ctx, |chat| ctx.client.send_message(chat, keyword))
+            .await?;
+        Ok(())
+    }
+
+    async fn next_page(&self, ctx: &Context, msg: &MessageExt) -> Result<Page> {
+        click_next(ctx, msg, &NEXT_LABELS).await
+    }
+}
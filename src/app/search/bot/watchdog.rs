@@ -2,74 +2,181 @@ use std::{sync::Arc, time::Duration};
 
 use anyhow::Result;
 use async_trait::async_trait;
+use chrono::Local;
+use cron::Schedule;
 use tokio::{
     sync::Mutex,
     time::{Instant, Interval},
 };
 use tracing::{info, warn};
 
-use crate::{app::search::bot::BOT_RESP_TIMEOUT, context::Context, PrintError, Runable};
+use crate::{
+    app::search::bot::BOT_RESP_TIMEOUT, context::Context, supervisor::RestartPolicy, PrintError,
+    Runable,
+};
+
+use super::{engine::SearchEngine, SearchRun};
 
-use super::engine::GenericEngine;
+/// 尚未收到任何结果页时，最多重发查询的次数
+const MAX_RESENDS: usize = 3;
+/// 共用查询锁时，机器人安静此时长后才让下一个关键词查询
+const QUIET_PERIOD: Duration = Duration::from_secs(30);
 
 pub struct Watchdog {
-    engine: GenericEngine,
-    keyword: &'static str,
-    last_update: Arc<Mutex<Instant>>,
+    engine: Arc<dyn SearchEngine>,
+    keyword: Arc<str>,
+    run: Arc<Mutex<SearchRun>>,
     bot_resend_tick: Arc<Mutex<Interval>>,
+    /// 搜索完成后按计划新建搜索
+    schedule: Option<Schedule>,
 }
 
 impl Watchdog {
     pub fn new(
-        engine: GenericEngine,
-        keyword: &'static str,
-        last_update: Arc<Mutex<Instant>>,
+        engine: Arc<dyn SearchEngine>,
+        keyword: Arc<str>,
+        run: Arc<Mutex<SearchRun>>,
         bot_resend_tick: Arc<Mutex<Interval>>,
     ) -> Self {
         Watchdog {
             engine,
             keyword,
-            last_update,
+            run,
             bot_resend_tick,
+            schedule: None,
         }
     }
-}
 
-#[async_trait]
-impl Runable for Watchdog {
-    fn name(&self) -> &'static str {
-        "搜索看门狗"
+    pub fn with_schedule(mut self, schedule: Option<Schedule>) -> Self {
+        self.schedule = schedule;
+        self
     }
-    async fn run(&mut self, ctx: Context) -> Result<()> {
+
+    /// 引擎有查询锁时持有锁进行[`Self::query`]
+    async fn watch(&self, ctx: &Context) -> Result<()> {
+        // 整个搜索期间持有，其他关键词等待
+        let Some(lock) = self.engine.query_lock() else {
+            return self.query(ctx).await;
+        };
+        let _query = tokio::select! {
+            guard = lock.lock() => guard,
+            _ = ctx.cancel.cancelled() => return Ok(()),
+        };
+        let ret = self.query(ctx).await;
+        // 放弃的搜索仍可能收到迟到的结果页，机器人安静后才交给下一个关键词，
+        // 否则这些结果会记到下一次搜索
+        self.wait_quiet(ctx).await;
+        ret
+    }
+
+    async fn wait_quiet(&self, ctx: &Context) {
+        loop {
+            let elapsed = self.run.lock().await.last_message.elapsed();
+            if elapsed >= QUIET_PERIOD {
+                return;
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(QUIET_PERIOD - elapsed) => (),
+                _ = ctx.cancel.cancelled() => return,
+            }
+        }
+    }
+
+    /// 发送查询并等待搜索完成或放弃
+    async fn query(&self, ctx: &Context) -> Result<()> {
+        let engine = self.engine.name();
+        let keyword = &*self.keyword;
         self.bot_resend_tick.lock().await.tick().await;
-        warn!(
-            engine = self.engine.name,
-            keyword = self.keyword,
-            "发送初始消息"
-        );
-        ctx.client
-            .send_message(self.engine.chat, self.keyword)
-            .await?;
+        warn!(engine, keyword, "发送初始消息");
+        {
+            let mut run = self.run.lock().await;
+            run.queried = true;
+            run.last_update = Instant::now();
+        }
+        self.engine.send_query(ctx, keyword, &self.run).await?;
 
-        let engine = self.engine.name;
-        let keyword = self.keyword;
         let mut count = 0;
+        let mut resends = 0;
         let mut ticker = tokio::time::interval(Duration::from_secs(7));
         loop {
             count += 1;
-            ticker.tick().await;
+            tokio::select! {
+                _ = ticker.tick() => (),
+                _ = ctx.cancel.cancelled() => return Ok(()),
+            }
             info!(count, engine, keyword, "WD检测");
-            let mut last = self.last_update.lock().await;
-            if tokio::time::Instant::now() - *last > BOT_RESP_TIMEOUT {
-                info!(count, engine, keyword, "搜索超时",);
-                info!(count, engine, keyword, "重发送消息");
-                self.bot_resend_tick.lock().await.tick().await;
-                ctx.client
-                    .send_message(self.engine.chat, self.keyword)
-                    .await
-                    .ok_or_warn();
-                *last = tokio::time::Instant::now();
+            let mut run = self.run.lock().await;
+            if run.finished {
+                return Ok(());
+            }
+            if Instant::now() - run.last_update <= BOT_RESP_TIMEOUT {
+                continue;
+            }
+            info!(count, engine, keyword, "搜索超时");
+            if run.search.pages > 0 {
+                // 要求翻到最后一页时无法区分最后一页与机器人停止响应
+                if run.search.exhaustive {
+                    return run.abandon(ctx).await;
+                }
+                // 已有结果页而机器人不再翻页，视为到达最后一页
+                return run.finish(ctx).await;
+            }
+            if resends >= MAX_RESENDS {
+                warn!(count, engine, keyword, resends, "机器人无响应，放弃搜索");
+                run.finished = true;
+                return Ok(());
+            }
+            run.last_update = Instant::now();
+            drop(run);
+            resends += 1;
+            info!(count, engine, keyword, resends, "重发送消息");
+            self.bot_resend_tick.lock().await.tick().await;
+            self.engine
+                .send_query(ctx, keyword, &self.run)
+                .await
+                .ok_or_warn();
+        }
+    }
+}
+
+#[async_trait]
+impl Runable for Watchdog {
+    fn name(&self) -> &'static str {
+        "搜索看门狗"
+    }
+
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::on_failure()
+    }
+
+    async fn run(&mut self, ctx: Context) -> Result<()> {
+        loop {
+            if !self.run.lock().await.finished {
+                self.watch(&ctx).await?;
+            }
+            if ctx.cancel.is_cancelled() {
+                return Ok(());
+            }
+            let Some(next) = self
+                .schedule
+                .as_ref()
+                .and_then(|schedule| schedule.upcoming(Local).next())
+            else {
+                return Ok(());
+            };
+            info!(
+                engine = self.engine.name(),
+                keyword = &*self.keyword,
+                next = %next,
+                "等待下次搜索"
+            );
+            let delay = (next - Local::now()).to_std().unwrap_or_default();
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => (),
+                _ = ctx.cancel.cancelled() => return Ok(()),
             }
+            let run = SearchRun::start(&ctx, self.engine.as_ref(), &self.keyword, true).await?;
+            *self.run.lock().await = run;
         }
     }
 }
@@ -1,4 +1,4 @@
 mod bot;
-pub use bot::SearchLink;
+pub use bot::{KeywordSearch, SearchLink};
 pub use bot::engine;
 
@@ -0,0 +1,512 @@
+//! 命令行参数
+
+use std::{collections::HashSet, io::Write, path::PathBuf, sync::Arc};
+
+use anyhow::{anyhow, bail, Result};
+use chrono::NaiveDateTime;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use grammers_client::types::PackedChat;
+use sea_orm::{ActiveEnum, EntityTrait};
+use tracing::{info, warn};
+
+use crate::{
+    app::{
+        self,
+        extract::manual,
+        search::{
+            engine::{parse_schedule, EngineKind},
+            KeywordSearch,
+        },
+    },
+    chat,
+    export::{Cursor, ExportFormat, ExportQuery, ExportTable},
+    graph::{self, GraphFormat},
+    login, metrics,
+    persist::{Database, FlushSinks},
+    Context, PrintError, Runable, SourceType,
+};
+
+const KEYWORDS: [&str; 5] = ["园区", "东南亚", "曝光", "担保公群", "需求"];
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Telegram灰产镜像")]
+pub struct Cli {
+    /// 缺省时以默认参数运行`run`
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// 运行镜像服务
+    Run(RunArgs),
+    /// 登陆并保存会话
+    Login,
+    /// 使用搜索机器人搜索关键词，直到收到退出信号
+    Search {
+        keyword: String,
+        /// `soso`、`jisou`或`inline:机器人用户名`
+        #[arg(long, default_value = "soso")]
+        engine: EngineKind,
+        /// 重复搜索的cron表达式，含秒字段，如`0 0 */6 * * *`
+        #[arg(long)]
+        schedule: Option<String>,
+    },
+    /// 比较同一机器人对关键词最近两次翻到最后一页的搜索，列出新出现与消失的链接
+    SearchDiff {
+        keyword: String,
+        /// 搜索机器人，即`search`表的`bot`列：`SOSO`、`jiso2bot`或内联机器人用户名
+        #[arg(long, default_value = "SOSO")]
+        bot: String,
+    },
+    /// 扫描一遍链接表中未解析的链接
+    ScanLinks,
+    /// 获取聊天的历史消息
+    Backfill {
+        /// 聊天id或用户名
+        chat: String,
+        #[arg(long, default_value_t = 1000)]
+        limit: usize,
+        /// 只获取比已存储消息更新的部分
+        #[arg(long)]
+        incremental: bool,
+    },
+    /// 以对话列表为准更新聊天的加入状态
+    SyncDialogs,
+    /// 导出数据
+    #[command(subcommand)]
+    Export(ExportCommand),
+    /// 各表的行数
+    Stats,
+    /// 建表并执行迁移
+    Migrate {
+        /// 由raw列补全旧消息的规范化列
+        #[arg(long)]
+        backfill_columns: bool,
+        /// 为旧版本写入的链接补全来源聊天
+        #[arg(long)]
+        backfill_source_chat: bool,
+        /// 每批处理的消息数
+        #[arg(long, default_value_t = 1000)]
+        batch: u64,
+    },
+    /// 手动添加链接，等待链接扫描解析
+    AddLink {
+        link: String,
+        #[arg(long, default_value = "")]
+        desc: String,
+    },
+    /// 手动添加用户名，等待链接扫描解析
+    AddUsername { username: String },
+    /// 从文件批量导入链接，每行一条链接或`@用户名`
+    ImportLinks { file: PathBuf },
+    /// 立即加入链接或用户名对应的聊天
+    Join { link: String },
+    /// 导入Telegram Desktop导出的result.json
+    Import { file: PathBuf },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ExportCommand {
+    /// 导出搜索、消息、链接、聊天组成的发现网络
+    Graph {
+        #[arg(long, value_enum, default_value_t = GraphFormat::Graphml)]
+        format: GraphFormat,
+        /// 缺省时输出到标准输出
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        /// 如`2024-10-01T00:00:00`
+        #[arg(long)]
+        since: Option<NaiveDateTime>,
+        #[arg(long)]
+        until: Option<NaiveDateTime>,
+        /// 只导出由此关键词的搜索发现的节点
+        #[arg(long)]
+        keyword: Option<String>,
+    },
+    /// 流式导出表中的行
+    Rows {
+        #[arg(value_enum)]
+        table: ExportTable,
+        #[arg(long, value_enum, default_value_t = ExportFormat::Jsonl)]
+        format: ExportFormat,
+        #[arg(long, short)]
+        output: PathBuf,
+        /// 只导出这些聊天，可重复
+        #[arg(long = "chat")]
+        chat_ids: Vec<i64>,
+        #[arg(long)]
+        since: Option<NaiveDateTime>,
+        #[arg(long)]
+        until: Option<NaiveDateTime>,
+        /// 文本包含此关键词
+        #[arg(long)]
+        keyword: Option<String>,
+        /// 来源类型，如`search`、`link`、`message`、`chat`
+        #[arg(long)]
+        source: Option<String>,
+        /// 游标文件，存在时只导出上次之后新增的行，导出后更新
+        #[arg(long)]
+        cursor: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum AppKind {
+    /// 健康检查与指标
+    Metrics,
+    /// 维护退出的聊天
+    Eliminate,
+    /// 主动扫描数据库链接表
+    ScanLinks,
+    /// 解析转发来源频道
+    ScanForward,
+    /// 主动搜索
+    Search,
+    /// 实时镜像更新
+    Live,
+    /// 聊天事件记录与成员数跳变检查
+    Events,
+    /// 转发到审核频道，需在配置文件中设置review
+    Review,
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct RunArgs {
+    /// 不启动的应用，逗号分隔
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub skip: Vec<AppKind>,
+    /// 搜索的关键词，可重复
+    #[arg(long = "keyword")]
+    pub keywords: Vec<String>,
+    /// 命令行关键词使用的搜索引擎，`soso`、`jisou`或`inline:机器人用户名`
+    #[arg(long, default_value = "soso")]
+    pub engine: EngineKind,
+}
+
+impl Cli {
+    pub async fn exec(self) -> Result<()> {
+        match self.command.unwrap_or(Command::Run(RunArgs::default())) {
+            Command::Run(args) => run(args).await,
+            Command::Login => {
+                tracing_subscriber::fmt::init();
+                let client = login::login_with_dotenv(None).await?;
+                login::save_session(&client)
+            }
+            Command::Search {
+                keyword,
+                engine,
+                schedule,
+            } => {
+                let schedule = schedule.as_deref().map(parse_schedule).transpose()?;
+                let ctx = Context::new().await?;
+                let engine = engine.build(&ctx).await?;
+                let search = KeywordSearch::new(engine, keyword.into()).with_schedule(schedule);
+                ctx.add_app(app::SearchLink::with_searches(std::iter::once(search)))
+                    .await;
+                if !ctx.sinks.is_empty() {
+                    ctx.add_runable(FlushSinks).await;
+                }
+                ctx.start_update_parser().await;
+                ctx.run().await
+            }
+            Command::ScanLinks => {
+                let ctx = Context::new().await?;
+                app::ScanLink::once().run(ctx.clone()).await?;
+                ctx.flush_sinks().await;
+                login::save_session(&ctx.client)
+            }
+            Command::Backfill {
+                chat,
+                limit,
+                incremental,
+            } => {
+                let ctx = Context::new().await?;
+                let packed = resolve_chat(&ctx, &chat).await?;
+                let min_id = if incremental {
+                    ctx.persist.find_last_msg_id(packed.id).await?
+                } else {
+                    None
+                };
+                app::History::after(packed, min_id, limit)
+                    .run(ctx.clone())
+                    .await?;
+                ctx.flush_sinks().await;
+                login::save_session(&ctx.client)
+            }
+            Command::SyncDialogs => {
+                let ctx = Context::new().await?;
+                let count = ctx.persist.sync_chat_joined(ctx.clone()).await?;
+                info!(count, "同步对话列表完成");
+                login::save_session(&ctx.client)
+            }
+            Command::Export(command) => export(command).await,
+            Command::SearchDiff { keyword, bot } => {
+                let db = Database::new().await?;
+                let Some(diff) = db.search_diff(&bot, &keyword).await? else {
+                    println!("{bot}对关键词{keyword}翻到最后一页的搜索不足两次");
+                    return Ok(());
+                };
+                println!(
+                    "{} ({}) -> {} ({})",
+                    diff.previous.start_time,
+                    diff.previous.id,
+                    diff.latest.start_time,
+                    diff.latest.id
+                );
+                for link in &diff.appeared {
+                    println!("+ {}\t{}", link.link, link.desc);
+                }
+                for link in &diff.disappeared {
+                    println!("- {}\t{}", link.link, link.desc);
+                }
+                Ok(())
+            }
+            Command::Stats => {
+                let db = Database::new().await?;
+                println!("{}", db.stats().await?);
+                Ok(())
+            }
+            Command::Migrate {
+                backfill_columns,
+                backfill_source_chat,
+                batch,
+            } => {
+                tracing_subscriber::fmt::init();
+                let db = Database::new().await?;
+                info!("迁移完成");
+                if backfill_columns {
+                    let updated = db.backfill_message_columns(batch).await?;
+                    info!(updated, "消息列补全完成");
+                }
+                if backfill_source_chat {
+                    let updated = db.backfill_link_source_chat().await?;
+                    info!(updated, "链接来源聊天补全完成");
+                }
+                Ok(())
+            }
+            Command::AddLink { link, desc } => {
+                tracing_subscriber::fmt::init();
+                let db = Database::new().await?;
+                manual::add_link(&db, &link, &desc).await?;
+                Ok(())
+            }
+            Command::AddUsername { username } => {
+                tracing_subscriber::fmt::init();
+                let db = Database::new().await?;
+                manual::add_username(&db, &username).await?;
+                Ok(())
+            }
+            Command::ImportLinks { file } => {
+                tracing_subscriber::fmt::init();
+                let db = Database::new().await?;
+                let count = manual::import_links(&db, &file).await?;
+                info!(count, "导入链接完成");
+                Ok(())
+            }
+            Command::Join { link } => {
+                let ctx = Context::new().await?;
+                match manual::join(&ctx, &link).await? {
+                    Some(joined) => {
+                        info!(chat_id = joined.id(), chat_name = joined.name(), "加入聊天")
+                    }
+                    None => warn!(link, "未能加入聊天"),
+                }
+                ctx.flush_sinks().await;
+                login::save_session(&ctx.client)
+            }
+            Command::Import { file } => {
+                tracing_subscriber::fmt::init();
+                let db = Database::new().await?;
+                let stats = app::mirror::import::import_desktop(&db, &file).await?;
+                info!(
+                    chats = stats.chats,
+                    messages = stats.messages,
+                    skipped = stats.skipped,
+                    links = stats.links,
+                    "导入完成"
+                );
+                Ok(())
+            }
+        }
+    }
+}
+
+async fn run(args: RunArgs) -> Result<()> {
+    println!("你好世界!");
+
+    let skip: HashSet<AppKind> = args.skip.into_iter().collect();
+    let enabled = |app: AppKind| !skip.contains(&app);
+    let ctx = Context::new().await?;
+
+    if enabled(AppKind::Metrics) {
+        ctx.add_runable(metrics::MetricsServer::new()).await;
+    }
+    if enabled(AppKind::Eliminate) {
+        ctx.add_runable(app::mirror::eliminate::Sentence::new())
+            .await;
+    }
+    if enabled(AppKind::ScanLinks) {
+        ctx.add_runable(app::ScanLink::new()).await;
+    }
+    if enabled(AppKind::ScanForward) {
+        ctx.add_runable(app::ScanForward::new()).await;
+    }
+    if enabled(AppKind::Search) {
+        let mut searches = Vec::new();
+        if args.keywords.is_empty() && !ctx.config.searches.is_empty() {
+            for search in &ctx.config.searches {
+                let schedule = search.schedule.as_deref().map(parse_schedule).transpose()?;
+                if let Some(engine) = search.engine.build(&ctx).await.ok_or_warn() {
+                    searches.push(
+                        KeywordSearch::new(engine, search.keyword.as_str().into())
+                            .with_schedule(schedule),
+                    );
+                }
+            }
+        } else {
+            let engine = args.engine.build(&ctx).await?;
+            let keywords: Vec<Arc<str>> = if args.keywords.is_empty() {
+                KEYWORDS.iter().map(|&k| k.into()).collect()
+            } else {
+                args.keywords.into_iter().map(Into::into).collect()
+            };
+            searches.extend(
+                keywords
+                    .into_iter()
+                    .map(|k| KeywordSearch::new(engine.clone(), k)),
+            );
+        }
+        ctx.add_app(app::SearchLink::with_searches(searches.into_iter()))
+            .await;
+    }
+    if enabled(AppKind::Live) {
+        let live_mirror = ctx
+            .config
+            .live_filter
+            .clone()
+            .map(app::LiveMirror::with_filter)
+            .unwrap_or_default()
+            .with_alerts(ctx.config.alerts.clone());
+        if let Some(delivery) = live_mirror.alert_delivery() {
+            ctx.add_runable(delivery).await;
+        }
+        ctx.add_parser(live_mirror).await;
+    }
+    if enabled(AppKind::Events) {
+        let event_mirror = ctx
+            .config
+            .event_filter
+            .clone()
+            .map(app::EventMirror::with_filter)
+            .unwrap_or_default();
+        ctx.add_parser(event_mirror).await;
+        ctx.add_runable(app::MemberCountWatch::default()).await;
+    }
+    if enabled(AppKind::Review) {
+        if let Some(review) = ctx.config.review.clone() {
+            let (forward_mirror, sender) = app::ForwardMirror::new(review);
+            ctx.add_runable(sender).await;
+            ctx.add_parser(forward_mirror).await;
+        } else {
+            warn!("配置文件中未设置review，不启动审核频道转发");
+        }
+    }
+
+    if !ctx.sinks.is_empty() {
+        ctx.add_runable(FlushSinks).await;
+    }
+
+    // 启动所有更新
+    ctx.start_update_parser().await;
+    ctx.run().await
+}
+
+async fn export(command: ExportCommand) -> Result<()> {
+    match command {
+        ExportCommand::Graph {
+            format,
+            output,
+            since,
+            until,
+            keyword,
+        } => {
+            let db = Database::new().await?;
+            let query = graph::GraphQuery {
+                since,
+                until,
+                keyword,
+            };
+            let graph = graph::Graph::load(&db, &query).await?;
+            match output {
+                Some(path) => {
+                    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+                    graph.write(format, &mut file)?;
+                    file.flush()?;
+                }
+                None => graph.write(format, &mut std::io::stdout().lock())?,
+            }
+            Ok(())
+        }
+        ExportCommand::Rows {
+            table,
+            format,
+            output,
+            chat_ids,
+            since,
+            until,
+            keyword,
+            source,
+            cursor,
+        } => {
+            tracing_subscriber::fmt::init();
+            let source = match source {
+                Some(s) => {
+                    Some(SourceType::try_from_value(&s).map_err(|_| anyhow!("未知的来源类型{s}"))?)
+                }
+                None => None,
+            };
+            let mut saved = match &cursor {
+                Some(path) => Cursor::load(path)?,
+                None => Cursor::default(),
+            };
+            let query = ExportQuery {
+                chat_ids,
+                since,
+                until,
+                keyword,
+                source,
+                after: saved.get(table),
+            };
+            let db = Database::new().await?;
+            let (count, last) = db.export(table, format, &query, &output).await?;
+            info!(table = table.name(), count, last, "导出完成");
+            if let Some(path) = &cursor {
+                saved.set(table, last);
+                saved.save(path)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// 聊天id须已存储；用户名优先查找已存储的聊天
+async fn resolve_chat(ctx: &Context, chat: &str) -> Result<PackedChat> {
+    if let Ok(chat_id) = chat.parse::<i64>() {
+        return match chat::Entity::find_by_id(chat_id)
+            .one(&ctx.persist.db)
+            .await?
+        {
+            Some(model) => model.packed(),
+            None => bail!("不存在chat_id{chat_id}"),
+        };
+    }
+    let username = chat.trim_start_matches('@');
+    if let Some(model) = ctx.persist.find_chat(Some(username)).await? {
+        return model.packed();
+    }
+    match ctx.resolve_username(username).await? {
+        Some(found) => Ok(found.pack()),
+        None => bail!("未找到用户名{username}"),
+    }
+}
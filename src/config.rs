@@ -0,0 +1,71 @@
+//! 运行时配置，由`CONFIG_FILE`指定的ron文件加载
+
+use anyhow::Result;
+use dotenv_codegen::dotenv;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    app::{
+        alert::AlertRule,
+        mirror::forward::ForwardConfig,
+        search::engine::{SearchBots, SearchConfig},
+    },
+    filter::Filter,
+    persist::HttpSinkConfig,
+};
+
+/// 留空或文件不存在时使用默认配置
+pub const CONFIG_FILE: &str = dotenv!("CONFIG_FILE");
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// 增量镜像的过滤器，缺省为[`crate::app::LiveMirror`]的默认过滤器
+    pub live_filter: Option<Filter>,
+    /// 聊天事件记录的消息过滤器，缺省为[`crate::app::EventMirror`]的默认过滤器
+    pub event_filter: Option<Filter>,
+    /// 搜索结果的附加过滤器，须与各搜索引擎自身的过滤器同时满足
+    pub search_filter: Option<Filter>,
+    /// 远程持久化，仅在设置了`PERSIST_URL`时生效
+    pub remote: HttpSinkConfig,
+    /// 增量镜像收到新消息时的告警规则
+    pub alerts: Vec<AlertRule>,
+    /// 转发到审核频道，缺省为不启用
+    pub review: Option<ForwardConfig>,
+    /// 未在命令行指定关键词时的搜索，为空时使用内置关键词
+    pub searches: Vec<SearchConfig>,
+    /// 内置搜索引擎的机器人用户名，解析结果缓存在chat表
+    pub search_bots: SearchBots,
+}
+
+impl Config {
+    pub fn load() -> Result<Self> {
+        if CONFIG_FILE.is_empty() {
+            info!("未配置CONFIG_FILE，使用默认配置");
+            return Ok(Self::default());
+        }
+        match std::fs::read_to_string(CONFIG_FILE) {
+            Ok(s) => {
+                info!(path = CONFIG_FILE, "加载配置文件");
+                Ok(ron::from_str(&s)?)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                warn!(path = CONFIG_FILE, "配置文件不存在，使用默认配置");
+                Ok(Self::default())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_config_parses() {
+        let config: Config = ron::from_str(include_str!("../config.example.ron")).unwrap();
+        assert!(config.live_filter.is_some());
+    }
+}
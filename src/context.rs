@@ -1,34 +1,52 @@
-use std::{ops::Deref, sync::Arc, time::Duration};
+use std::{future::Future, ops::Deref, sync::Arc, time::Duration};
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use dotenv_codegen::dotenv;
 use grammers_client::{
-    types::{Chat, PackedChat},
+    grammers_tl_types as tl,
+    types::{chat::PackedType, Chat, PackedChat},
     Client, InvocationError,
 };
-use sea_orm::EntityTrait;
+use sea_orm::{EntityTrait, TryIntoModel};
 use tokio::{
-    sync::{Mutex, RwLock},
-    task::JoinSet,
+    sync::{mpsc, Mutex, RwLock},
+    task::{AbortHandle, JoinError, JoinHandle, JoinSet},
 };
+use tokio_util::sync::CancellationToken;
 use tracing::{error, info, level_filters::STATIC_MAX_LEVEL, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 use crate::{
     chat,
-    persist::Database,
-    update::{UpdateApp, Updater},
-    App, PrintError, Runable, Source,
+    config::Config,
+    metrics,
+    persist::{Database, HttpSink, Persist, Record},
+    supervisor::{self, RestartPolicy, Supervisor, TaskStatus},
+    types::chat_edge::ChatEdgeKind,
+    update::{SaveUpdateState, UpdateApp, Updater},
+    user, App, PrintError, Runable, Source,
 };
 
+/// 收到退出信号后，等待各任务完成当前工作的最长时间
+pub const SHUTDOWN_DEADLINE: Duration = Duration::from_secs(30);
+
 #[derive(Clone)]
 pub struct Context(Arc<ContextInner>);
 
 pub struct ContextInner {
     pub client: Client,
     pub persist: Database,
+    /// 写入`persist`后再收到记录副本的其他后端
+    pub sinks: Vec<Arc<dyn Persist>>,
     pub interval: IntervalSet,
-    background_tasks: Mutex<JoinSet<()>>,
+    pub config: Config,
+    /// 退出信号，各[`Runable`]应在完成当前工作后检查并退出
+    pub cancel: CancellationToken,
+    pub supervisor: Supervisor,
+    /// 新启动的后台任务，交给[`Context::run`]等待
+    task_tx: mpsc::UnboundedSender<JoinHandle<()>>,
+    /// 只由[`Context::run`]持有，添加任务不需要获取此锁
+    task_rx: Mutex<mpsc::UnboundedReceiver<JoinHandle<()>>>,
     update: RwLock<UpdateApp>,
 }
 
@@ -42,8 +60,8 @@ impl Deref for Context {
 
 impl Context {
     pub async fn new() -> Result<Self> {
-        #[cfg_attr(not(feature = "grafana"), allow(unused_mut))]
-        let mut background_tasks = JoinSet::new();
+        let (task_tx, task_rx) = mpsc::unbounded_channel();
+        let cancel = CancellationToken::new();
 
         let logger = tracing_subscriber::registry();
         let loki_url = dotenv!("LOKI_URL");
@@ -61,9 +79,14 @@ impl Context {
                     .label("version", std::env::var("CARGO_PKG_VERSION").unwrap())?
                     .build_url(Url::parse(&loki_url)?)?;
 
-                background_tasks.spawn(async move {
-                    task.await;
+                let cancel = cancel.clone();
+                let task = tokio::spawn(async move {
+                    tokio::select! {
+                        _ = task => (),
+                        _ = cancel.cancelled() => (),
+                    }
                 });
+                task_tx.send(task).ok_or_log();
                 logger.with(layer).init();
             }
             #[cfg(not(feature = "grafana"))]
@@ -72,12 +95,25 @@ impl Context {
             logger.init();
         }
 
+        let config = Config::load()?;
+        let persist = Database::new().await?;
+        let mut sinks: Vec<Arc<dyn Persist>> = Vec::new();
+        if let Some(sink) = HttpSink::from_dotenv(config.remote.clone())? {
+            sinks.push(Arc::new(sink));
+        }
+        let update_state = persist.load_update_state().await?;
+
         let ret = Self(Arc::new(ContextInner {
-            client: crate::login::login_with_dotenv().await?,
-            background_tasks: Mutex::new(background_tasks),
-            persist: Database::new().await?,
+            client: crate::login::login_with_dotenv(update_state).await?,
+            task_tx,
+            task_rx: Mutex::new(task_rx),
+            persist,
+            sinks,
             update: RwLock::new(UpdateApp::new()),
             interval: Default::default(),
+            config,
+            cancel,
+            supervisor: Default::default(),
         }));
 
         Ok(ret)
@@ -91,13 +127,67 @@ impl Context {
         }
     }
 
-    pub async fn add_runable(&self, mut value: impl Runable) -> () {
+    /// 添加后台任务，按照任务自身的[`Runable::restart_policy`]托管
+    pub async fn add_runable(&self, value: impl Runable) -> () {
+        let policy = value.restart_policy();
+        self.add_runable_with(value, policy).await;
+    }
+
+    /// 添加后台任务，使用指定的重启策略托管
+    pub async fn add_runable_with(&self, value: impl Runable, policy: RestartPolicy) {
         let ctx = self.clone();
         let name = value.name();
-        self.background_tasks.lock().await.spawn(async move {
-            value.run(ctx).await.into_log();
-            warn!(name, "任务退出");
+        let handle = self.supervisor.register(name, policy);
+        let task = tokio::spawn(async move {
+            supervisor::supervise(ctx, value, policy, handle).await;
+            warn!(name, "任务结束");
         });
+        self.task_tx.send(task).ok_or_log();
+    }
+
+    /// 写入数据库，再将写入后的行发给其他后端，返回写入后的行及其是否为新记录
+    pub async fn put<T>(&self, model: T) -> Result<(T, bool)>
+    where
+        T: Into<Record> + TryFrom<Record, Error = anyhow::Error>,
+    {
+        let (stored, created) = Persist::put(&self.persist, model.into()).await?;
+        self.publish(stored.clone()).await;
+        Ok((stored.try_into()?, created))
+    }
+
+    /// 写入消息发送者，`min`用户只更新`last_seen`，不能按整行写入
+    pub async fn put_sender(&self, sender: user::ActiveModel, min: bool) -> Result<()> {
+        if min {
+            let (stored, _) = self.persist.put_user(sender, true).await?;
+            self.publish(Record::User(stored)).await;
+        } else {
+            self.put(sender.try_into_model()?).await?;
+        }
+        Ok(())
+    }
+
+    /// 将已写入数据库的记录发给其他后端
+    pub async fn publish(&self, record: Record) {
+        for sink in &self.sinks {
+            sink.put(record.clone())
+                .await
+                .map_err(|e| anyhow!("{}后端写入失败 >> {e}", sink.name()))
+                .ok_or_warn();
+        }
+    }
+
+    pub async fn flush_sinks(&self) {
+        for sink in &self.sinks {
+            sink.flush()
+                .await
+                .map_err(|e| anyhow!("{}后端刷新失败 >> {e}", sink.name()))
+                .ok_or_warn();
+        }
+    }
+
+    /// 全部后台任务的运行状态
+    pub fn task_states(&self) -> Vec<TaskStatus> {
+        self.supervisor.states()
     }
 
     pub async fn add_parser(&self, value: impl Updater) -> () {
@@ -110,21 +200,77 @@ impl Context {
         let mut buf = UpdateApp::new();
         std::mem::swap(&mut *updater, &mut buf);
         self.add_runable(buf).await;
+        self.add_runable(SaveUpdateState).await;
     }
 
-    /// Run until error occurs. Return first error.
+    /// Run until all tasks finished or a shutdown signal (SIGINT/SIGTERM) arrives.
+    ///
+    /// On shutdown, every task gets [`SHUTDOWN_DEADLINE`] to finish its current
+    /// unit of work before being aborted, then the session is flushed.
     pub async fn run(self) -> Result<()> {
-        while let Some(result) = self.background_tasks.lock().await.join_next().await {
-            result.ok_or_log();
+        let cancel = self.cancel.clone();
+        tokio::spawn(async move {
+            shutdown_signal().await;
+            warn!("收到退出信号");
+            cancel.cancel();
+        });
+
+        let mut task_rx = self.task_rx.lock().await;
+        let mut tasks = BackgroundTasks::default();
+        loop {
+            tasks.receive(&mut task_rx);
+            tokio::select! {
+                result = tasks.join_next() => match result {
+                    Some(result) => log_task_result(result),
+                    None => break,
+                },
+                Some(task) = task_rx.recv() => tasks.push(task),
+                _ = self.cancel.cancelled() => break,
+            }
+        }
+
+        if self.cancel.is_cancelled() {
+            let deadline = SHUTDOWN_DEADLINE.as_secs();
+            warn!(deadline, "等待任务结束");
+            let drain = async {
+                loop {
+                    // 退出过程中添加的任务同样需要等待
+                    tasks.receive(&mut task_rx);
+                    match tasks.join_next().await {
+                        Some(result) => log_task_result(result),
+                        None => break,
+                    }
+                }
+            };
+            if tokio::time::timeout(SHUTDOWN_DEADLINE, drain)
+                .await
+                .is_err()
+            {
+                tasks.receive(&mut task_rx);
+                warn!(remain = tasks.len(), "等待超时，强制结束剩余任务");
+                tasks.shutdown().await;
+            }
         }
         warn!("全部任务结束");
+
+        self.save_update_state().await?;
+        crate::login::save_session(&self.client)?;
+        Ok(())
+    }
+
+    /// 将客户端当前的更新状态写入数据库，供下次启动时补齐更新
+    pub async fn save_update_state(&self) -> Result<()> {
+        self.client.sync_update_state();
+        if let Some(state) = self.client.session().get_state() {
+            self.persist.save_update_state(&state).await?;
+        }
         Ok(())
     }
 
     pub async fn resolve_username(&self, username: &str) -> Result<Option<Chat>> {
         self.interval.resolve_username.tick().await;
         let mut ret = self.client.resolve_username(username).await;
-        if wait_on_flood(&ret).await.is_some() {
+        if wait_on_flood("resolve_username", &ret).await.is_some() {
             warn!("重新尝试");
             self.interval.resolve_username.tick().await;
             ret = self.client.resolve_username(username).await;
@@ -141,7 +287,7 @@ impl Context {
         self.interval.quit_chat.tick().await;
 
         let mut ret = self.client.delete_dialog(chat).await;
-        if wait_on_flood(&ret).await.is_some() {
+        if wait_on_flood("delete_dialog", &ret).await.is_some() {
             warn!("重新尝试");
             self.interval.join_chat.tick().await;
             ret = self.client.delete_dialog(chat).await;
@@ -149,6 +295,7 @@ impl Context {
         let ret = ret.ok_or_log();
         if ret.is_some() {
             self.persist.set_chat_quited(id).await?;
+            metrics::CHAT_QUITS.inc();
             warn!(chat_id = id, "退出聊天成功");
         } else {
             warn!(chat_id = id, "退出聊天失败");
@@ -166,12 +313,83 @@ impl Context {
             ret = self.join_chat_raw(chat).await;
         };
         let ret = ret?;
-        self.persist
-            .put_chat(chat::ActiveModel::from_chat(&ret, true, source))
+        self.put(chat::ActiveModel::from_chat(&ret, true, source).try_into_model()?)
             .await?;
+        if chat.is_channel() {
+            self.record_linked_chat(chat).await.ok_or_warn();
+        }
         Ok(ret)
     }
 
+    /// 记录频道与其讨论组的关系
+    pub async fn record_linked_chat(&self, channel: PackedChat) -> Result<()> {
+        let Some(input) = channel.try_to_input_channel() else {
+            return Ok(());
+        };
+        let mut ret = self
+            .client
+            .invoke(&tl::functions::channels::GetFullChannel {
+                channel: input.clone(),
+            })
+            .await;
+        if wait_on_flood("get_full_channel", &ret).await.is_some() {
+            warn!("重新尝试");
+            ret = self
+                .client
+                .invoke(&tl::functions::channels::GetFullChannel { channel: input })
+                .await;
+        }
+        let tl::enums::messages::ChatFull::Full(full) = ret?;
+        if let tl::enums::ChatFull::ChannelFull(full) = full.full_chat {
+            if let Some(linked) = full.linked_chat_id {
+                // 讨论组的linked_chat_id指向其频道
+                let (from, to) = match channel.ty {
+                    PackedType::Megagroup => (linked, channel.id),
+                    _ => (channel.id, linked),
+                };
+                self.persist
+                    .put_chat_edge(from, to, ChatEdgeKind::LinkedDiscussion)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 群组或频道的成员数，无法获取时返回None
+    pub async fn member_count(&self, chat: PackedChat) -> Result<Option<i32>> {
+        self.interval.full_chat.tick().await;
+        if let Some(input) = chat.try_to_input_channel() {
+            let request = tl::functions::channels::GetFullChannel { channel: input };
+            let mut ret = self.client.invoke(&request).await;
+            if wait_on_flood("get_full_channel", &ret).await.is_some() {
+                warn!("重新尝试");
+                ret = self.client.invoke(&request).await;
+            }
+            let tl::enums::messages::ChatFull::Full(full) = ret?;
+            return Ok(match full.full_chat {
+                tl::enums::ChatFull::ChannelFull(full) => full.participants_count,
+                _ => None,
+            });
+        }
+        if chat.ty != PackedType::Chat {
+            return Ok(None);
+        }
+        let request = tl::functions::messages::GetFullChat { chat_id: chat.id };
+        let mut ret = self.client.invoke(&request).await;
+        if wait_on_flood("get_full_chat", &ret).await.is_some() {
+            warn!("重新尝试");
+            ret = self.client.invoke(&request).await;
+        }
+        let tl::enums::messages::ChatFull::Full(full) = ret?;
+        Ok(match full.full_chat {
+            tl::enums::ChatFull::Full(tl::types::ChatFull {
+                participants: tl::enums::ChatParticipants::Participants(p),
+                ..
+            }) => Some(p.participants.len() as i32),
+            _ => None,
+        })
+    }
+
     pub async fn join_quited_chat(&self, chat_id: i64) -> Result<Chat> {
         let db = &self.persist.db;
         let chat = chat::Entity::find_by_id(chat_id).one(db).await?;
@@ -202,8 +420,8 @@ impl Context {
         }
 
         if let Some(chat) = chat? {
-            self.persist
-                .put_chat(chat::ActiveModel::from_chat(&chat, true, source))
+            metrics::CHAT_JOINS.inc();
+            self.put(chat::ActiveModel::from_chat(&chat, true, source).try_into_model()?)
                 .await?;
             info!(link, "加入邀请链接成功");
             return Ok(Some(chat));
@@ -217,13 +435,14 @@ impl Context {
         self.interval.join_chat.tick().await;
         let chat = Into::<PackedChat>::into(chat);
         let mut ret = self.client.join_chat(chat).await;
-        if wait_on_flood(&ret).await.is_some() {
+        if wait_on_flood("join_chat", &ret).await.is_some() {
             warn!("重新尝试");
             self.interval.join_chat.tick().await;
             ret = self.client.join_chat(chat).await;
         }
         match ret {
             Ok(Some(chat)) => {
+                metrics::CHAT_JOINS.inc();
                 warn!(chat_name = chat.name(), chat_id = chat.id(), "加入聊天");
                 Ok(chat)
             }
@@ -288,6 +507,10 @@ pub struct IntervalSet {
     pub unpack_chat: Interval,
     pub resolve_username: Interval,
     pub find_msg: Interval,
+    /// 向审核频道发送
+    pub forward_msg: Interval,
+    /// 获取聊天完整信息
+    pub full_chat: Interval,
 }
 impl Default for IntervalSet {
     fn default() -> Self {
@@ -297,11 +520,87 @@ impl Default for IntervalSet {
             resolve_username: Interval::from_secs(60),
             unpack_chat: Interval::from_millis(500),
             find_msg: Interval::from_millis(15),
+            forward_msg: Interval::from_secs(3),
+            full_chat: Interval::from_secs(2),
+        }
+    }
+}
+
+/// [`Context::run`]等待中的后台任务
+#[derive(Default)]
+struct BackgroundTasks {
+    /// 等待各任务的[`JoinHandle`]
+    waiting: JoinSet<Result<(), JoinError>>,
+    /// 中止等待不会中止任务本身，超时时通过此句柄中止
+    aborts: Vec<AbortHandle>,
+}
+
+impl BackgroundTasks {
+    fn push(&mut self, task: JoinHandle<()>) {
+        self.aborts.retain(|a| !a.is_finished());
+        self.aborts.push(task.abort_handle());
+        self.waiting.spawn(task);
+    }
+
+    /// 取出已添加但尚未等待的任务
+    fn receive(&mut self, rx: &mut mpsc::UnboundedReceiver<JoinHandle<()>>) {
+        while let Ok(task) = rx.try_recv() {
+            self.push(task);
+        }
+    }
+
+    async fn join_next(&mut self) -> Option<Result<Result<(), JoinError>, JoinError>> {
+        self.waiting.join_next().await
+    }
+
+    fn len(&self) -> usize {
+        self.waiting.len()
+    }
+
+    async fn shutdown(&mut self) {
+        for abort in self.aborts.drain(..) {
+            abort.abort();
         }
+        self.waiting.shutdown().await;
+    }
+}
+
+fn log_task_result(result: Result<Result<(), JoinError>, JoinError>) {
+    if let Err(e) = result.and_then(|r| r) {
+        error!("{}", e);
+    }
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Some(mut terminate) = signal(SignalKind::terminate()).ok_or_log() {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => (),
+                _ = terminate.recv() => (),
+            }
+            return;
+        }
+    }
+    tokio::signal::ctrl_c().await.ok_or_log();
+}
+
+/// 遇到FLOOD_WAIT时休眠后重试一次
+pub async fn retry_on_flood<T, F, Fut>(method: &str, f: F) -> Result<T>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, InvocationError>>,
+{
+    let mut ret = f().await;
+    if wait_on_flood(method, &ret).await.is_some() {
+        warn!(method, "重新尝试");
+        ret = f().await;
     }
+    Ok(ret?)
 }
 
-async fn wait_on_flood<T>(result: &Result<T, InvocationError>) -> Option<()> {
+async fn wait_on_flood<T>(method: &str, result: &Result<T, InvocationError>) -> Option<()> {
     let e = if let Err(e) = result { e } else { return None };
     match e {
         InvocationError::Rpc(e) => {
@@ -309,7 +608,10 @@ async fn wait_on_flood<T>(result: &Result<T, InvocationError>) -> Option<()> {
                 // TODO: 添加and逻辑，e.name.eq(name of FLOOD)
                 warn!("捕获服务器警告FLOOD_WAIT");
                 if let Some(cooldown) = e.value {
-                    warn!(cooldown, "尝试休眠");
+                    warn!(method, cooldown, "尝试休眠");
+                    metrics::FLOOD_WAIT_SECONDS
+                        .with_label_values(&[method])
+                        .inc_by(cooldown as u64);
                     tokio::time::sleep(Duration::from_secs(cooldown as u64)).await;
                     warn!(cooldown, "结束休眠");
                     return Some(());
@@ -0,0 +1,459 @@
+//! 数据导出
+//!
+//! 按`seq`列分批流式读取`message`、`chat`、`link`、`search`表，写入JSONL、CSV或Parquet。
+//! 每次导出后将最大的`seq`写入游标文件，下次导出只读取之后新增或更新的行。
+//! 只导出取号事务均已结束的`seq`，避免未提交的较小`seq`在游标之后才出现而被跳过。
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use arrow::{
+    datatypes::{DataType, Field, Schema, SchemaRef},
+    json::reader::{infer_json_schema_from_iterator, ReaderBuilder},
+};
+use chrono::NaiveDateTime;
+use clap::ValueEnum;
+use parquet::arrow::ArrowWriter;
+use sea_orm::{prelude::*, DbBackend, FromQueryResult, Statement, Value};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use tracing::info;
+
+use crate::{
+    persist::{escape_like, Database},
+    types::SourceType,
+};
+
+const BATCH: i64 = 1000;
+/// 取得`seq`到事务分配事务号之间的间隔远小于此
+const SEQ_SETTLE: Duration = Duration::from_secs(1);
+/// 等待进行中的事务结束时的查询间隔
+const SETTLE_POLL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportTable {
+    Message,
+    Chat,
+    Link,
+    Search,
+}
+
+impl ExportTable {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExportTable::Message => "message",
+            ExportTable::Chat => "chat",
+            ExportTable::Link => "link",
+            ExportTable::Search => "search",
+        }
+    }
+
+    /// 按聊天过滤的列
+    fn chat_column(&self) -> Option<&'static str> {
+        match self {
+            ExportTable::Message | ExportTable::Chat => Some("chat_id"),
+            ExportTable::Link => Some("source_chat_id"),
+            ExportTable::Search => None,
+        }
+    }
+
+    /// 按时间过滤的列
+    fn date_column(&self) -> Option<&'static str> {
+        match self {
+            ExportTable::Message => Some("date"),
+            ExportTable::Chat => Some("last_update"),
+            ExportTable::Link => None,
+            ExportTable::Search => Some("start_time"),
+        }
+    }
+
+    /// 按关键词过滤的列
+    fn text_columns(&self) -> &'static [&'static str] {
+        match self {
+            ExportTable::Message => &["text"],
+            ExportTable::Chat => &["name"],
+            ExportTable::Link => &["link", "desc"],
+            ExportTable::Search => &["keyword"],
+        }
+    }
+
+    fn has_source(&self) -> bool {
+        !matches!(self, ExportTable::Search)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    Jsonl,
+    Csv,
+    Parquet,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExportQuery {
+    /// 为空时导出全部聊天
+    pub chat_ids: Vec<i64>,
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+    /// 文本包含此关键词
+    pub keyword: Option<String>,
+    pub source: Option<SourceType>,
+    /// 只导出`seq`大于此值的行
+    pub after: i64,
+}
+
+/// 各表上次导出到的`seq`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Cursor(BTreeMap<String, i64>);
+
+impl Cursor {
+    /// 文件不存在时从头导出
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(s) => Ok(serde_json::from_str(&s)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn get(&self, table: ExportTable) -> i64 {
+        self.0.get(table.name()).copied().unwrap_or(0)
+    }
+
+    pub fn set(&mut self, table: ExportTable, seq: i64) {
+        self.0.insert(table.name().to_string(), seq);
+    }
+}
+
+impl Database {
+    /// 已分配的最大`seq`，返回时取得不超过它的`seq`的事务均已结束
+    async fn export_horizon(&self, table: ExportTable) -> Result<i64> {
+        let row = JsonValue::find_by_statement(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            r#"SELECT coalesce(
+                pg_sequence_last_value(pg_get_serial_sequence($1, 'seq')::regclass), 0
+            ) AS "horizon""#,
+            [table.name().into()],
+        ))
+        .one(&self.db)
+        .await?
+        .ok_or(anyhow!("{}表缺少seq序列", table.name()))?;
+        let horizon = row["horizon"].as_i64().unwrap_or_default();
+
+        // 此后仍未结束的事务都已分配事务号，等到它们全部结束
+        tokio::time::sleep(SEQ_SETTLE).await;
+        let row = JsonValue::find_by_statement(Statement::from_string(
+            DbBackend::Postgres,
+            r#"SELECT pg_current_snapshot()::text AS "snapshot""#,
+        ))
+        .one(&self.db)
+        .await?
+        .ok_or(anyhow!("无法读取事务快照"))?;
+        let snapshot = row["snapshot"].as_str().unwrap_or_default().to_string();
+        loop {
+            let row = JsonValue::find_by_statement(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                r#"SELECT pg_snapshot_xmin(pg_current_snapshot())
+                    >= pg_snapshot_xmax($1::pg_snapshot) AS "settled""#,
+                [snapshot.clone().into()],
+            ))
+            .one(&self.db)
+            .await?;
+            if row.is_some_and(|row| row["settled"].as_bool() == Some(true)) {
+                break;
+            }
+            tokio::time::sleep(SETTLE_POLL).await;
+        }
+        Ok(horizon)
+    }
+
+    /// 读取一批`seq`大于`after`且不超过`horizon`的行
+    async fn export_batch(
+        &self,
+        table: ExportTable,
+        query: &ExportQuery,
+        after: i64,
+        horizon: i64,
+    ) -> Result<Vec<JsonValue>> {
+        let mut values: Vec<Value> = vec![after.into(), horizon.into()];
+        let mut conds = vec![r#""seq" > $1"#.to_string(), r#""seq" <= $2"#.to_string()];
+
+        if let Some(column) = table.chat_column() {
+            if !query.chat_ids.is_empty() {
+                values.push(query.chat_ids.clone().into());
+                conds.push(format!(r#""{column}" = ANY(${})"#, values.len()));
+            }
+        }
+        if let Some(column) = table.date_column() {
+            if let Some(since) = query.since {
+                values.push(since.into());
+                conds.push(format!(r#""{column}" >= ${}"#, values.len()));
+            }
+            if let Some(until) = query.until {
+                values.push(until.into());
+                conds.push(format!(r#""{column}" < ${}"#, values.len()));
+            }
+        }
+        if let Some(keyword) = &query.keyword {
+            values.push(format!("%{}%", escape_like(keyword)).into());
+            let n = values.len();
+            let any = table
+                .text_columns()
+                .iter()
+                .map(|column| format!(r#""{column}" ILIKE ${n}"#))
+                .collect::<Vec<_>>()
+                .join(" OR ");
+            conds.push(format!("({any})"));
+        }
+        if let Some(source) = query.source {
+            if table.has_source() {
+                values.push(source.into_value().into());
+                conds.push(format!(r#""source" = ${}"#, values.len()));
+            }
+        }
+        values.push(BATCH.into());
+
+        let sql = format!(
+            r#"SELECT * FROM "{}" WHERE {} ORDER BY "seq" LIMIT ${}"#,
+            table.name(),
+            conds.join(" AND "),
+            values.len()
+        );
+        let ret = JsonValue::find_by_statement(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            sql,
+            values,
+        ))
+        .all(&self.db)
+        .await?;
+        Ok(ret)
+    }
+
+    /// 表的列名，没有数据时用于写出表头
+    async fn export_columns(&self, table: ExportTable) -> Result<Vec<String>> {
+        let rows = JsonValue::find_by_statement(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            r#"SELECT "column_name" FROM "information_schema"."columns"
+                WHERE "table_schema" = current_schema() AND "table_name" = $1
+                ORDER BY "ordinal_position""#,
+            [table.name().into()],
+        ))
+        .all(&self.db)
+        .await?;
+        Ok(rows
+            .iter()
+            .filter_map(|r| r["column_name"].as_str().map(str::to_string))
+            .collect())
+    }
+
+    /// 导出到文件，返回导出的行数与最大的`seq`
+    pub async fn export(
+        &self,
+        table: ExportTable,
+        format: ExportFormat,
+        query: &ExportQuery,
+        path: impl AsRef<Path>,
+    ) -> Result<(u64, i64)> {
+        let mut writer = RowWriter::new(format, File::create(path)?);
+        let horizon = self.export_horizon(table).await?;
+        let mut after = query.after;
+        let mut count = 0;
+        loop {
+            let rows = self.export_batch(table, query, after, horizon).await?;
+            let Some(last) = rows.last() else {
+                break;
+            };
+            after = last["seq"]
+                .as_i64()
+                .ok_or(anyhow!("{}表缺少seq列", table.name()))?;
+            count += rows.len() as u64;
+            writer.write(rows)?;
+            info!(table = table.name(), count, after, "导出");
+        }
+        if count == 0 {
+            writer.write_empty(&self.export_columns(table).await?)?;
+        }
+        writer.finish()?;
+        Ok((count, after))
+    }
+}
+
+enum RowWriter {
+    Jsonl(BufWriter<File>),
+    Csv {
+        writer: csv::Writer<File>,
+        header: Option<Vec<String>>,
+    },
+    Parquet {
+        file: Option<File>,
+        writer: Option<(SchemaRef, ArrowWriter<File>)>,
+    },
+}
+
+impl RowWriter {
+    fn new(format: ExportFormat, file: File) -> Self {
+        match format {
+            ExportFormat::Jsonl => Self::Jsonl(BufWriter::new(file)),
+            ExportFormat::Csv => Self::Csv {
+                writer: csv::Writer::from_writer(file),
+                header: None,
+            },
+            ExportFormat::Parquet => Self::Parquet {
+                file: Some(file),
+                writer: None,
+            },
+        }
+    }
+
+    fn write(&mut self, rows: Vec<JsonValue>) -> Result<()> {
+        match self {
+            Self::Jsonl(out) => {
+                for row in rows {
+                    serde_json::to_writer(&mut *out, &row)?;
+                    out.write_all(b"\n")?;
+                }
+            }
+            Self::Csv { writer, header } => {
+                for row in rows {
+                    let JsonValue::Object(row) = row else {
+                        continue;
+                    };
+                    if header.is_none() {
+                        let keys: Vec<String> = row.keys().cloned().collect();
+                        writer.write_record(keys.iter())?;
+                        *header = Some(keys);
+                    }
+                    let header = header.as_ref().expect("已写入表头");
+                    writer.write_record(header.iter().map(|k| csv_field(row.get(k))))?;
+                }
+            }
+            Self::Parquet { file, writer } => {
+                // 嵌套的值（如raw列）以JSON字符串保存
+                let rows: Vec<JsonValue> = rows.into_iter().map(flatten_row).collect();
+                if writer.is_none() {
+                    let schema = parquet_schema(&rows)?;
+                    let file = file.take().ok_or(anyhow!("Parquet文件已关闭"))?;
+                    let arrow_writer = ArrowWriter::try_new(file, schema.clone(), None)?;
+                    *writer = Some((schema, arrow_writer));
+                }
+                let (schema, arrow_writer) = writer.as_mut().expect("已初始化");
+                let rows: Vec<JsonValue> =
+                    rows.into_iter().map(|r| coerce_row(r, schema)).collect();
+                let mut decoder = ReaderBuilder::new(schema.clone()).build_decoder()?;
+                decoder.serialize(&rows)?;
+                if let Some(batch) = decoder.flush()? {
+                    arrow_writer.write(&batch)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 没有数据时写出表头或只含结构的Parquet文件，列均视为可空字符串
+    fn write_empty(&mut self, columns: &[String]) -> Result<()> {
+        // 与有数据时相同，按JSON对象的键序排列
+        let row: serde_json::Map<String, JsonValue> = columns
+            .iter()
+            .map(|c| (c.clone(), JsonValue::Null))
+            .collect();
+        match self {
+            Self::Jsonl(_) => (),
+            Self::Csv { writer, header } => {
+                if header.is_none() {
+                    writer.write_record(row.keys())?;
+                }
+            }
+            Self::Parquet { file, writer } => {
+                if writer.is_none() {
+                    let fields: Vec<Field> = row
+                        .keys()
+                        .map(|k| Field::new(k, DataType::Utf8, true))
+                        .collect();
+                    let schema: SchemaRef = Arc::new(Schema::new(fields));
+                    let file = file.take().ok_or(anyhow!("Parquet文件已关闭"))?;
+                    let arrow_writer = ArrowWriter::try_new(file, schema.clone(), None)?;
+                    *writer = Some((schema, arrow_writer));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            Self::Jsonl(mut out) => out.flush()?,
+            Self::Csv { mut writer, .. } => writer.flush()?,
+            Self::Parquet { writer, .. } => {
+                if let Some((_, writer)) = writer {
+                    writer.close()?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn csv_field(value: Option<&JsonValue>) -> String {
+    match value {
+        None | Some(JsonValue::Null) => String::new(),
+        Some(JsonValue::String(s)) => s.clone(),
+        Some(other) => other.to_string(),
+    }
+}
+
+fn flatten_row(row: JsonValue) -> JsonValue {
+    match row {
+        JsonValue::Object(row) => JsonValue::Object(
+            row.into_iter()
+                .map(|(k, v)| match v {
+                    JsonValue::Object(_) | JsonValue::Array(_) => (k, v.to_string().into()),
+                    v => (k, v),
+                })
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+/// 由第一批数据推断，全为空的列视为字符串
+fn parquet_schema(rows: &[JsonValue]) -> Result<SchemaRef> {
+    let inferred = infer_json_schema_from_iterator(rows.iter().map(|r| Ok(r.clone())))?;
+    let fields: Vec<Field> = inferred
+        .fields()
+        .iter()
+        .map(|f| match f.data_type() {
+            DataType::Null => Field::new(f.name(), DataType::Utf8, true),
+            _ => f.as_ref().clone().with_nullable(true),
+        })
+        .collect();
+    Ok(Arc::new(Schema::new(fields)))
+}
+
+/// 字符串列中出现的其他类型转为字符串
+fn coerce_row(row: JsonValue, schema: &SchemaRef) -> JsonValue {
+    let JsonValue::Object(mut row) = row else {
+        return row;
+    };
+    for field in schema.fields() {
+        if field.data_type() == &DataType::Utf8 {
+            if let Some(v) = row.get_mut(field.name()) {
+                if !v.is_string() && !v.is_null() {
+                    *v = v.to_string().into();
+                }
+            }
+        }
+    }
+    JsonValue::Object(row)
+}
@@ -0,0 +1,375 @@
+//! 消息过滤器
+//!
+//! [`Filter`]可自由组合，并可通过ron配置文件加载，例如：
+//!
+//! ```ron
+//! And([
+//!     Incoming,
+//!     Not(BotChat),
+//!     Or([Word("担保"), Regex("园区|东南亚")]),
+//! ])
+//! ```
+
+use std::{collections::HashSet, fmt::Debug};
+
+use chrono::{DateTime, Utc};
+use grammers_client::{
+    grammers_tl_types as tl,
+    types::{Chat, Message as RawMessage},
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::types::{chat::ChatType, peer_id};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Filter {
+    /// 全部通过
+    All,
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+    /// 非本账号发出的消息
+    Incoming,
+    /// 服务消息，如成员加入、标题修改
+    Service,
+    ChatId(HashSet<i64>),
+    ChatType(HashSet<ChatType>),
+    /// 与机器人的私聊
+    BotChat,
+    Sender(HashSet<i64>),
+    /// 文本包含子串
+    Word(String),
+    Regex(Pattern),
+    HasMedia,
+    /// 文本中包含链接
+    HasLinks,
+    /// 转发自指定的聊天或用户
+    ForwardedFrom(HashSet<i64>),
+    DateRange {
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+    },
+    /// ISO 639-3语言代码，如`cmn`、`eng`
+    Language(HashSet<String>),
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::Incoming
+    }
+}
+
+impl Filter {
+    pub fn chat_id(id: i64) -> Self {
+        Self::ChatId(HashSet::from([id]))
+    }
+
+    pub fn word(word: impl Into<String>) -> Self {
+        Self::Word(word.into())
+    }
+
+    pub fn not(self) -> Self {
+        Self::Not(Box::new(self))
+    }
+
+    pub fn and(self, other: Filter) -> Self {
+        match self {
+            Self::And(mut inner) => {
+                inner.push(other);
+                Self::And(inner)
+            }
+            this => Self::And(vec![this, other]),
+        }
+    }
+
+    pub fn or(self, other: Filter) -> Self {
+        match self {
+            Self::Or(mut inner) => {
+                inner.push(other);
+                Self::Or(inner)
+            }
+            this => Self::Or(vec![this, other]),
+        }
+    }
+
+    /// * return true this message will get parsed later;
+    /// * return false will ignore this message
+    pub fn matches(&self, raw_msg: &RawMessage) -> bool {
+        self.matches_target(raw_msg)
+    }
+
+    fn matches_target(&self, msg: &impl Target) -> bool {
+        match self {
+            Filter::All => true,
+            Filter::And(inner) => inner.iter().all(|f| f.matches_target(msg)),
+            Filter::Or(inner) => inner.iter().any(|f| f.matches_target(msg)),
+            Filter::Not(inner) => !inner.matches_target(msg),
+            Filter::Incoming => !msg.outgoing(),
+            Filter::Service => msg.is_service(),
+            Filter::ChatId(ids) => ids.contains(&msg.chat_id()),
+            Filter::ChatType(types) => types.contains(&msg.chat_type()),
+            Filter::BotChat => msg.is_bot_chat(),
+            Filter::Sender(ids) => msg.sender_id().is_some_and(|id| ids.contains(&id)),
+            Filter::Word(word) => msg.text().contains(word.as_str()),
+            Filter::Regex(pattern) => pattern.0.is_match(msg.text()),
+            Filter::HasMedia => msg.has_media(),
+            Filter::HasLinks => msg.has_links(),
+            Filter::ForwardedFrom(ids) => msg.forwarded_from().is_some_and(|id| ids.contains(&id)),
+            Filter::DateRange { since, until } => {
+                let date = msg.date();
+                since.is_none_or(|since| date >= since) && until.is_none_or(|until| date < until)
+            }
+            Filter::Language(langs) => {
+                whatlang::detect(msg.text()).is_some_and(|info| langs.contains(info.lang().code()))
+            }
+        }
+    }
+}
+
+/// 过滤器读取的消息属性
+trait Target {
+    fn outgoing(&self) -> bool;
+    fn is_service(&self) -> bool;
+    fn chat_id(&self) -> i64;
+    fn chat_type(&self) -> ChatType;
+    fn is_bot_chat(&self) -> bool;
+    fn sender_id(&self) -> Option<i64>;
+    fn text(&self) -> &str;
+    fn has_media(&self) -> bool;
+    fn has_links(&self) -> bool;
+    fn forwarded_from(&self) -> Option<i64>;
+    fn date(&self) -> DateTime<Utc>;
+}
+
+impl Target for RawMessage {
+    fn outgoing(&self) -> bool {
+        RawMessage::outgoing(self)
+    }
+    fn is_service(&self) -> bool {
+        self.action().is_some()
+    }
+    fn chat_id(&self) -> i64 {
+        self.chat().id()
+    }
+    fn chat_type(&self) -> ChatType {
+        ChatType::from(&self.chat())
+    }
+    fn is_bot_chat(&self) -> bool {
+        match self.chat() {
+            Chat::User(u) => u.is_bot(),
+            _ => false,
+        }
+    }
+    fn sender_id(&self) -> Option<i64> {
+        self.sender().map(|s| s.id())
+    }
+    fn text(&self) -> &str {
+        RawMessage::text(self)
+    }
+    fn has_media(&self) -> bool {
+        self.media().is_some()
+    }
+    fn has_links(&self) -> bool {
+        has_links(self)
+    }
+    fn forwarded_from(&self) -> Option<i64> {
+        forwarded_from(self)
+    }
+    fn date(&self) -> DateTime<Utc> {
+        RawMessage::date(self)
+    }
+}
+
+fn has_links(raw_msg: &RawMessage) -> bool {
+    raw_msg.raw.entities.as_ref().is_some_and(|ents| {
+        ents.iter().any(|ent| {
+            matches!(
+                ent,
+                tl::enums::MessageEntity::Url(_) | tl::enums::MessageEntity::TextUrl(_)
+            )
+        })
+    })
+}
+
+/// 转发来源的聊天或用户id
+pub fn forwarded_from(raw_msg: &RawMessage) -> Option<i64> {
+    let tl::enums::MessageFwdHeader::Header(header) = raw_msg.forward_header()?;
+    header.from_id.as_ref().map(peer_id)
+}
+
+/// 可序列化的正则表达式，配置中以字符串表示
+#[derive(Clone)]
+pub struct Pattern(pub regex::Regex);
+
+impl Debug for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.as_str().fmt(f)
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        regex::Regex::new(&s)
+            .map(Pattern)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    struct Msg {
+        outgoing: bool,
+        chat_id: i64,
+        chat_type: ChatType,
+        bot_chat: bool,
+        sender_id: Option<i64>,
+        text: &'static str,
+        date: DateTime<Utc>,
+    }
+
+    impl Default for Msg {
+        fn default() -> Self {
+            Self {
+                outgoing: false,
+                chat_id: 1,
+                chat_type: ChatType::Group,
+                bot_chat: false,
+                sender_id: Some(10),
+                text: "",
+                date: Utc.with_ymd_and_hms(2024, 10, 1, 0, 0, 0).unwrap(),
+            }
+        }
+    }
+
+    impl Target for Msg {
+        fn outgoing(&self) -> bool {
+            self.outgoing
+        }
+        fn is_service(&self) -> bool {
+            false
+        }
+        fn chat_id(&self) -> i64 {
+            self.chat_id
+        }
+        fn chat_type(&self) -> ChatType {
+            self.chat_type
+        }
+        fn is_bot_chat(&self) -> bool {
+            self.bot_chat
+        }
+        fn sender_id(&self) -> Option<i64> {
+            self.sender_id
+        }
+        fn text(&self) -> &str {
+            self.text
+        }
+        fn has_media(&self) -> bool {
+            false
+        }
+        fn has_links(&self) -> bool {
+            false
+        }
+        fn forwarded_from(&self) -> Option<i64> {
+            None
+        }
+        fn date(&self) -> DateTime<Utc> {
+            self.date
+        }
+    }
+
+    fn parse(s: &str) -> Filter {
+        ron::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn ron_combinators() {
+        let filter =
+            parse(r#"And([Incoming, Not(BotChat), Or([Word("担保"), Regex("园区|东南亚")])])"#);
+        let msg = |text| Msg {
+            text,
+            ..Default::default()
+        };
+        assert!(filter.matches_target(&msg("东南亚招聘")));
+        assert!(filter.matches_target(&msg("担保交易")));
+        assert!(!filter.matches_target(&msg("普通消息")));
+        assert!(!filter.matches_target(&Msg {
+            outgoing: true,
+            ..msg("担保交易")
+        }));
+        assert!(!filter.matches_target(&Msg {
+            bot_chat: true,
+            ..msg("担保交易")
+        }));
+    }
+
+    #[test]
+    fn ron_sets() {
+        let filter = parse("And([ChatId([1, 2]), ChatType([Group, Channel]), Sender([10])])");
+        assert!(filter.matches_target(&Msg::default()));
+        assert!(!filter.matches_target(&Msg {
+            chat_id: 3,
+            ..Default::default()
+        }));
+        assert!(!filter.matches_target(&Msg {
+            chat_type: ChatType::User,
+            ..Default::default()
+        }));
+        assert!(!filter.matches_target(&Msg {
+            sender_id: None,
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn date_range_is_half_open() {
+        let filter = parse(
+            r#"DateRange(since: Some("2024-10-01T00:00:00Z"), until: Some("2024-10-02T00:00:00Z"))"#,
+        );
+        let at = |d| Msg {
+            date: Utc.with_ymd_and_hms(2024, 10, d, 0, 0, 0).unwrap(),
+            ..Default::default()
+        };
+        assert!(filter.matches_target(&at(1)));
+        assert!(!filter.matches_target(&at(2)));
+        assert!(!filter.matches_target(&Msg {
+            date: Utc.with_ymd_and_hms(2024, 9, 30, 23, 59, 59).unwrap(),
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn invalid_regex_is_rejected() {
+        assert!(ron::from_str::<Filter>(r#"Regex("(")"#).is_err());
+    }
+
+    #[test]
+    fn builders_flatten() {
+        let filter = Filter::Incoming
+            .and(Filter::chat_id(1))
+            .and(Filter::word("a"));
+        assert!(matches!(&filter, Filter::And(inner) if inner.len() == 3));
+        let filter = Filter::word("a")
+            .or(Filter::word("b"))
+            .or(Filter::word("c"));
+        assert!(matches!(&filter, Filter::Or(inner) if inner.len() == 3));
+        assert!(filter.matches_target(&Msg {
+            text: "c",
+            ..Default::default()
+        }));
+        assert!(!filter.not().matches_target(&Msg {
+            text: "c",
+            ..Default::default()
+        }));
+    }
+}
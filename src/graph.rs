@@ -0,0 +1,326 @@
+//! 发现网络导出
+//!
+//! 沿[`Source`](crate::Source)记录的来源，从搜索出发遍历 搜索 -> 消息 -> 链接 -> 聊天，
+//! 导出为GraphML、GEXF或DOT，可直接由Gephi打开。
+
+use std::{
+    collections::HashSet,
+    io::{self, Write},
+};
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use clap::ValueEnum;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+
+use crate::{
+    persist::Database,
+    types::{chat, link, message, search, SourceType},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GraphFormat {
+    Graphml,
+    Gexf,
+    Dot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Search,
+    Message,
+    Link,
+    Chat,
+}
+
+impl NodeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NodeKind::Search => "search",
+            NodeKind::Message => "message",
+            NodeKind::Link => "link",
+            NodeKind::Chat => "chat",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub id: String,
+    pub kind: NodeKind,
+    pub label: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Edge {
+    pub source: String,
+    pub target: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GraphQuery {
+    /// 只保留此时间之后的搜索与消息
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+    /// 只保留由此关键词的搜索发现的节点
+    pub keyword: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+fn search_node(id: i32) -> String {
+    format!("search:{id}")
+}
+fn message_node(chat_id: i64, msg_id: i32) -> String {
+    format!("message:{chat_id}:{msg_id}")
+}
+fn link_node(id: i32) -> String {
+    format!("link:{id}")
+}
+fn chat_node(id: i64) -> String {
+    format!("chat:{id}")
+}
+
+impl Graph {
+    /// 从符合条件的搜索出发遍历来源记录
+    pub async fn load(persist: &Database, query: &GraphQuery) -> Result<Self> {
+        let db = &persist.db;
+        let mut ret = Self::default();
+
+        let mut searches = search::Entity::find();
+        if let Some(keyword) = &query.keyword {
+            searches = searches.filter(search::Column::Keyword.eq(keyword.as_str()));
+        }
+        if let Some(since) = query.since {
+            searches = searches.filter(search::Column::StartTime.gte(since));
+        }
+        if let Some(until) = query.until {
+            searches = searches.filter(search::Column::StartTime.lt(until));
+        }
+        let searches = searches.all(db).await?;
+        let search_ids: Vec<i32> = searches.iter().map(|s| s.id).collect();
+        for s in searches {
+            ret.nodes.push(Node {
+                id: search_node(s.id),
+                kind: NodeKind::Search,
+                label: format!("{} @{}", s.keyword, s.bot),
+            });
+        }
+
+        let mut messages = message::Entity::find()
+            .filter(message::Column::Source.eq(SourceType::Search))
+            .filter(message::Column::SourceId.is_in(search_ids.iter().map(|&id| i64::from(id))));
+        if let Some(since) = query.since {
+            messages = messages.filter(message::Column::Date.gte(since));
+        }
+        if let Some(until) = query.until {
+            messages = messages.filter(message::Column::Date.lt(until));
+        }
+        let messages = messages.all(db).await?;
+        let msg_ids: HashSet<i32> = messages.iter().map(|m| m.msg_id).collect();
+        let mut msg_nodes: Vec<(i64, i32, String)> = Vec::new();
+        for m in messages {
+            let id = message_node(m.chat_id, m.msg_id);
+            ret.edges.push(Edge {
+                source: search_node(m.source_id as i32),
+                target: id.clone(),
+            });
+            ret.nodes.push(Node {
+                id: id.clone(),
+                kind: NodeKind::Message,
+                label: m.msg_id.to_string(),
+            });
+            msg_nodes.push((m.chat_id, m.msg_id, id));
+        }
+
+        let links = link::Entity::find()
+            .filter(link::Column::Source.eq(SourceType::Message))
+            .filter(link::Column::SourceId.is_in(msg_ids.iter().map(|&id| i64::from(id))))
+            .all(db)
+            .await?;
+        let mut link_ids: Vec<i32> = Vec::new();
+        for l in links {
+            let id = link_node(l.id);
+            let mut matched = false;
+            for (chat_id, msg_id, node) in msg_nodes.iter() {
+                // 旧版本的链接来源没有聊天编号
+                if i64::from(*msg_id) == l.source_id
+                    && l.source_chat_id.is_none_or(|id| id == *chat_id)
+                {
+                    matched = true;
+                    ret.edges.push(Edge {
+                        source: node.clone(),
+                        target: id.clone(),
+                    });
+                }
+            }
+            // 编号相同但来自其他聊天的消息，不属于本图
+            if !matched {
+                continue;
+            }
+            link_ids.push(l.id);
+            ret.nodes.push(Node {
+                id,
+                kind: NodeKind::Link,
+                label: l.link,
+            });
+        }
+
+        // 经链接发现的聊天，及由这些聊天继续发现的聊天
+        let mut chats = chat::Entity::find()
+            .filter(chat::Column::Source.eq(SourceType::Link))
+            .filter(chat::Column::SourceId.is_in(link_ids.iter().map(|&id| i64::from(id))))
+            .all(db)
+            .await?;
+        let mut seen = HashSet::new();
+        while !chats.is_empty() {
+            let mut frontier = Vec::new();
+            for c in chats {
+                if !seen.insert(c.chat_id) {
+                    continue;
+                }
+                let parent = match c.source {
+                    SourceType::Link => link_node(c.source_id as i32),
+                    _ => chat_node(c.source_id),
+                };
+                ret.edges.push(Edge {
+                    source: parent,
+                    target: chat_node(c.chat_id),
+                });
+                ret.nodes.push(Node {
+                    id: chat_node(c.chat_id),
+                    kind: NodeKind::Chat,
+                    label: c.name,
+                });
+                frontier.push(c.chat_id);
+            }
+            chats = chat::Entity::find()
+                .filter(chat::Column::Source.eq(SourceType::Chat))
+                .filter(chat::Column::SourceId.is_in(frontier))
+                .all(db)
+                .await?;
+        }
+
+        Ok(ret)
+    }
+
+    pub fn write(&self, format: GraphFormat, out: &mut impl Write) -> io::Result<()> {
+        match format {
+            GraphFormat::Graphml => self.write_graphml(out),
+            GraphFormat::Gexf => self.write_gexf(out),
+            GraphFormat::Dot => self.write_dot(out),
+        }
+    }
+
+    pub fn write_graphml(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            out,
+            r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+        )?;
+        writeln!(
+            out,
+            r#"  <key id="kind" for="node" attr.name="kind" attr.type="string"/>"#
+        )?;
+        writeln!(
+            out,
+            r#"  <key id="label" for="node" attr.name="label" attr.type="string"/>"#
+        )?;
+        writeln!(out, r#"  <graph id="discovery" edgedefault="directed">"#)?;
+        for node in &self.nodes {
+            writeln!(
+                out,
+                r#"    <node id="{}"><data key="kind">{}</data><data key="label">{}</data></node>"#,
+                escape_xml(&node.id),
+                node.kind.as_str(),
+                escape_xml(&node.label)
+            )?;
+        }
+        for (i, edge) in self.edges.iter().enumerate() {
+            writeln!(
+                out,
+                r#"    <edge id="e{i}" source="{}" target="{}"/>"#,
+                escape_xml(&edge.source),
+                escape_xml(&edge.target)
+            )?;
+        }
+        writeln!(out, "  </graph>")?;
+        writeln!(out, "</graphml>")
+    }
+
+    pub fn write_gexf(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(out, r#"<gexf xmlns="http://gexf.net/1.3" version="1.3">"#)?;
+        writeln!(out, r#"  <graph mode="static" defaultedgetype="directed">"#)?;
+        writeln!(out, r#"    <attributes class="node">"#)?;
+        writeln!(
+            out,
+            r#"      <attribute id="kind" title="kind" type="string"/>"#
+        )?;
+        writeln!(out, "    </attributes>")?;
+        writeln!(out, "    <nodes>")?;
+        for node in &self.nodes {
+            writeln!(
+                out,
+                r#"      <node id="{}" label="{}"><attvalues><attvalue for="kind" value="{}"/></attvalues></node>"#,
+                escape_xml(&node.id),
+                escape_xml(&node.label),
+                node.kind.as_str()
+            )?;
+        }
+        writeln!(out, "    </nodes>")?;
+        writeln!(out, "    <edges>")?;
+        for (i, edge) in self.edges.iter().enumerate() {
+            writeln!(
+                out,
+                r#"      <edge id="e{i}" source="{}" target="{}"/>"#,
+                escape_xml(&edge.source),
+                escape_xml(&edge.target)
+            )?;
+        }
+        writeln!(out, "    </edges>")?;
+        writeln!(out, "  </graph>")?;
+        writeln!(out, "</gexf>")
+    }
+
+    pub fn write_dot(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "digraph discovery {{")?;
+        for node in &self.nodes {
+            writeln!(
+                out,
+                r#"  "{}" [label="{}", kind="{}"];"#,
+                escape_dot(&node.id),
+                escape_dot(&node.label),
+                node.kind.as_str()
+            )?;
+        }
+        for edge in &self.edges {
+            writeln!(
+                out,
+                r#"  "{}" -> "{}";"#,
+                escape_dot(&edge.source),
+                escape_dot(&edge.target)
+            )?;
+        }
+        writeln!(out, "}}")
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    // XML 1.0不允许大部分控制字符
+    s.replace(|c: char| c.is_control() && c != '\n' && c != '\t', "")
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn escape_dot(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -2,7 +2,10 @@ use std::io::{self, BufRead};
 
 use anyhow::{anyhow, Result};
 use dotenv_codegen::dotenv;
-use grammers_client::{session::Session, Client, Config, InitParams, SignInError};
+use grammers_client::{
+    session::{types::UpdateState, Session},
+    Client, Config, InitParams, SignInError,
+};
 use tracing::{info, info_span, warn};
 
 // 编译时获取
@@ -12,7 +15,9 @@ const PHONE_NUMBER: &str = dotenv!("PHONE_NUMBER");
 const SESSION_FILE: &str = dotenv!("SESSION_FILE");
 const SOCKS5_PROXY: &str = dotenv!("SOCKS5_PROXY");
 
-pub async fn login_with_dotenv() -> Result<Client> {
+/// `update_state`为数据库中保存的更新状态，存在时开启`catch_up`，
+/// 连接后由客户端通过`updates.getDifference`与`updates.getChannelDifference`补齐离线期间的更新
+pub async fn login_with_dotenv(update_state: Option<UpdateState>) -> Result<Client> {
     let login_span = info_span!("客户端登陆");
     let _span = login_span.enter();
 
@@ -22,8 +27,19 @@ pub async fn login_with_dotenv() -> Result<Client> {
         info!("使用Socks5代理{}", SOCKS5_PROXY);
         let _ = params.proxy_url.insert(SOCKS5_PROXY.to_string());
     }
+
+    let session = Session::load_file_or_create(SESSION_FILE)?;
+    if let Some(state) = update_state {
+        info!(
+            pts = state.pts,
+            qts = state.qts,
+            "恢复更新状态，补齐离线期间的更新"
+        );
+        session.set_state(state);
+        params.catch_up = true;
+    }
     let config = Config {
-        session: Session::load_file_or_create(SESSION_FILE)?, //
+        session, //
         api_id: API_ID.parse()?,
         api_hash: API_HASH.to_string(),
         params,
@@ -71,3 +87,9 @@ pub async fn login_with_dotenv() -> Result<Client> {
     info!("会话已登陆");
     Ok(client)
 }
+
+pub fn save_session(client: &Client) -> Result<()> {
+    client.session().save_to_file(SESSION_FILE)?;
+    info!("会话已保存");
+    Ok(())
+}
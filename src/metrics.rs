@@ -0,0 +1,182 @@
+//! 可观测指标与健康检查
+//!
+//! 指标注册在prometheus默认注册表中，由[`MetricsServer`]通过HTTP暴露：
+//! * `/healthz` 进程存活
+//! * `/readyz` 数据库可用且没有任务彻底退出
+//! * `/metrics` Prometheus文本格式指标
+
+use std::sync::LazyLock;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use axum::{extract::State, http::StatusCode, routing::get, Router};
+use dotenv_codegen::dotenv;
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge_vec,
+    HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use tracing::{info, warn};
+
+use crate::{
+    supervisor::{Restart, RestartPolicy, TaskState},
+    Context, PrintError, Runable,
+};
+
+/// 留空为不启动指标服务
+pub const METRICS_ADDR: &str = dotenv!("METRICS_ADDR");
+
+pub static MESSAGES_MIRRORED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "gray_mirror_messages_mirrored_total",
+        "镜像的消息数量",
+        &["chat_id"]
+    )
+    .expect("指标注册失败")
+});
+
+pub static LINKS_DISCOVERED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!("gray_mirror_links_discovered_total", "发现的链接数量")
+        .expect("指标注册失败")
+});
+
+pub static LINKS_PARSED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "gray_mirror_links_parsed_total",
+        "成功解析的链接数量",
+        &["kind"]
+    )
+    .expect("指标注册失败")
+});
+
+pub static LINKS_FAILED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "gray_mirror_links_failed_total",
+        "解析失败的链接数量",
+        &["kind"]
+    )
+    .expect("指标注册失败")
+});
+
+pub static ALERTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "gray_mirror_alerts_total",
+        "告警规则命中后的投递结果",
+        &["rule", "status"]
+    )
+    .expect("指标注册失败")
+});
+
+pub static CHAT_JOINS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!("gray_mirror_chat_joins_total", "加入聊天次数").expect("指标注册失败")
+});
+
+pub static CHAT_QUITS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!("gray_mirror_chat_quits_total", "退出聊天次数").expect("指标注册失败")
+});
+
+pub static FLOOD_WAIT_SECONDS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "gray_mirror_flood_wait_seconds_total",
+        "因FLOOD_WAIT休眠的秒数",
+        &["method"]
+    )
+    .expect("指标注册失败")
+});
+
+pub static UPDATE_QUEUE_LEN: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "gray_mirror_update_queue_len",
+        "解析器队列中等待处理的更新数量",
+        &["parser"]
+    )
+    .expect("指标注册失败")
+});
+
+pub static UPDATE_LAGGED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "gray_mirror_update_lagged_total",
+        "解析器队列满载而丢弃的更新数量",
+        &["parser"]
+    )
+    .expect("指标注册失败")
+});
+
+pub static DB_LATENCY: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!("gray_mirror_db_latency_seconds", "数据库操作耗时", &["op"])
+        .expect("指标注册失败")
+});
+
+pub struct MetricsServer {}
+impl MetricsServer {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+#[async_trait]
+impl Runable for MetricsServer {
+    fn name(&self) -> &'static str {
+        "指标服务"
+    }
+
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::on_failure()
+    }
+
+    async fn run(&mut self, ctx: Context) -> Result<()> {
+        if METRICS_ADDR.is_empty() {
+            info!("未配置METRICS_ADDR，不启动指标服务");
+            return Ok(());
+        }
+
+        let app = Router::new()
+            .route("/healthz", get(healthz))
+            .route("/readyz", get(readyz))
+            .route("/metrics", get(metrics))
+            .with_state(ctx.clone());
+
+        let listener = tokio::net::TcpListener::bind(METRICS_ADDR).await?;
+        warn!(addr = METRICS_ADDR, "指标服务启动");
+        let cancel = ctx.cancel.clone();
+        axum::serve(listener, app)
+            .with_graceful_shutdown(async move { cancel.cancelled().await })
+            .await?;
+        Ok(())
+    }
+}
+
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+async fn readyz(State(ctx): State<Context>) -> (StatusCode, String) {
+    if ctx.persist.db.ping().await.ok_or_warn().is_none() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "database unavailable".into(),
+        );
+    }
+
+    let dead: Vec<_> = ctx
+        .task_states()
+        .into_iter()
+        // 不重启的任务出错退出属于一次性任务失败，不影响服务就绪
+        .filter(|s| s.state == TaskState::Dead && s.policy.restart != Restart::Never)
+        .map(|s| s.name)
+        .collect();
+    if !dead.is_empty() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("dead tasks: {}", dead.join(", ")),
+        );
+    }
+
+    (StatusCode::OK, "ok".into())
+}
+
+async fn metrics() -> (StatusCode, String) {
+    match TextEncoder::new().encode_to_string(&prometheus::gather()) {
+        Ok(body) => (StatusCode::OK, body),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
@@ -1,19 +1,91 @@
+use std::fmt::{Display, Formatter};
+
 use anyhow::{bail, Result};
 use dotenv_codegen::dotenv;
-use grammers_client::types::PackedChat;
+use grammers_client::{session::types::UpdateState, types::PackedChat};
 use sea_orm::{
-    prelude::*, sea_query::OnConflict, ConnectOptions, DbBackend, IntoActiveModel, Order,
-    QueryOrder, Schema, Set, Statement, TransactionTrait,
+    prelude::*, sea_query::OnConflict, Condition, ConnectOptions, DbBackend, FromQueryResult,
+    IntoActiveModel, Order, PaginatorTrait, QueryOrder, QuerySelect, QueryTrait, Schema, Set,
+    Statement, TransactionTrait, TryIntoModel,
 };
-use tracing::debug;
+use tracing::{debug, info, warn};
 
 use crate::{
-    types::{chat, link, message, search},
+    metrics,
+    types::{
+        alert, chat, chat_edge, chat_event, forward_candidate, link, message, search,
+        search_result, update_state, user, username_history, Source, SourceType,
+    },
     Context,
 };
 
+mod alert;
+mod fulltext;
+mod graph;
+mod http;
+mod provenance;
+mod search;
+mod sink;
+
+pub use fulltext::{escape_like, MessageHit, MessageQuery, SearchMode};
+pub use graph::EdgeQuery;
+pub use http::{Encoding, HttpSink, HttpSinkConfig};
+pub use provenance::Provenance;
+pub use search::SearchDiff;
+pub use sink::{FlushSinks, Persist, Record};
+
+/// 旧版本创建的表缺少的列
+const MIGRATIONS: [&str; 32] = [
+    r#"ALTER TABLE "message" ADD COLUMN IF NOT EXISTS "text" text"#,
+    r#"ALTER TABLE "message" ADD COLUMN IF NOT EXISTS "date" timestamp"#,
+    r#"ALTER TABLE "message" ADD COLUMN IF NOT EXISTS "sender_id" bigint"#,
+    r#"ALTER TABLE "message" ADD COLUMN IF NOT EXISTS "views" integer"#,
+    r#"ALTER TABLE "message" ADD COLUMN IF NOT EXISTS "forwards" integer"#,
+    r#"ALTER TABLE "message" ADD COLUMN IF NOT EXISTS "reply_to" integer"#,
+    r#"ALTER TABLE "message" ADD COLUMN IF NOT EXISTS "edit_date" timestamp"#,
+    r#"ALTER TABLE "message" ADD COLUMN IF NOT EXISTS "fwd_from_chat" bigint"#,
+    r#"ALTER TABLE "message" ADD COLUMN IF NOT EXISTS "fwd_from_msg" integer"#,
+    r#"ALTER TABLE "message" ADD COLUMN IF NOT EXISTS "grouped_id" bigint"#,
+    r#"ALTER TABLE "message" ADD COLUMN IF NOT EXISTS "post_author" text"#,
+    r#"ALTER TABLE "message" ADD COLUMN IF NOT EXISTS "media_type" text"#,
+    r#"ALTER TABLE "message" ADD COLUMN IF NOT EXISTS "source_chat_id" bigint"#,
+    r#"ALTER TABLE "link" ADD COLUMN IF NOT EXISTS "source_chat_id" bigint"#,
+    r#"ALTER TABLE "chat" ADD COLUMN IF NOT EXISTS "source_chat_id" bigint"#,
+    // 导出游标，新增的行自动取得更大的值
+    r#"ALTER TABLE "message" ADD COLUMN IF NOT EXISTS "seq" bigserial"#,
+    r#"ALTER TABLE "chat" ADD COLUMN IF NOT EXISTS "seq" bigserial"#,
+    r#"ALTER TABLE "link" ADD COLUMN IF NOT EXISTS "seq" bigserial"#,
+    r#"ALTER TABLE "search" ADD COLUMN IF NOT EXISTS "seq" bigserial"#,
+    // 行被更新时取得新的seq，之后的导出会再次包含它
+    r#"CREATE OR REPLACE FUNCTION "bump_seq"() RETURNS trigger AS $$
+    BEGIN
+        NEW."seq" := nextval(pg_get_serial_sequence(format('%I.%I', TG_TABLE_SCHEMA, TG_TABLE_NAME), 'seq'));
+        RETURN NEW;
+    END
+    $$ LANGUAGE plpgsql"#,
+    r#"DROP TRIGGER IF EXISTS "bump_seq" ON "message";
+    CREATE TRIGGER "bump_seq" BEFORE UPDATE ON "message" FOR EACH ROW EXECUTE FUNCTION "bump_seq"()"#,
+    r#"DROP TRIGGER IF EXISTS "bump_seq" ON "chat";
+    CREATE TRIGGER "bump_seq" BEFORE UPDATE ON "chat" FOR EACH ROW EXECUTE FUNCTION "bump_seq"()"#,
+    r#"DROP TRIGGER IF EXISTS "bump_seq" ON "link";
+    CREATE TRIGGER "bump_seq" BEFORE UPDATE ON "link" FOR EACH ROW EXECUTE FUNCTION "bump_seq"()"#,
+    r#"DROP TRIGGER IF EXISTS "bump_seq" ON "search";
+    CREATE TRIGGER "bump_seq" BEFORE UPDATE ON "search" FOR EACH ROW EXECUTE FUNCTION "bump_seq"()"#,
+    // 同一规则对同一消息只投递一次，消息编辑后不重复告警
+    r#"CREATE UNIQUE INDEX IF NOT EXISTS "alert_dedup" ON "alert" ("rule", "webhook", "chat_id", "msg_id")"#,
+    r#"ALTER TABLE "search" ADD COLUMN IF NOT EXISTS "pages" integer NOT NULL DEFAULT 0"#,
+    r#"ALTER TABLE "search" ADD COLUMN IF NOT EXISTS "results" integer NOT NULL DEFAULT 0"#,
+    r#"ALTER TABLE "search" ADD COLUMN IF NOT EXISTS "new_links" integer NOT NULL DEFAULT 0"#,
+    r#"ALTER TABLE "search" ADD COLUMN IF NOT EXISTS "known_links" integer NOT NULL DEFAULT 0"#,
+    r#"ALTER TABLE "search" ADD COLUMN IF NOT EXISTS "completed" boolean NOT NULL DEFAULT false"#,
+    r#"ALTER TABLE "search" ADD COLUMN IF NOT EXISTS "end_time" timestamp"#,
+    r#"ALTER TABLE "search" ADD COLUMN IF NOT EXISTS "exhaustive" boolean NOT NULL DEFAULT false"#,
+];
+
 pub struct Database {
     pub db: DatabaseConnection,
+    /// 消息全文搜索使用的匹配方式
+    pub search_mode: SearchMode,
 }
 impl Database {
     pub const DB_URL: &'static str = dotenv!("DATABASE_URL");
@@ -59,11 +131,84 @@ impl Database {
             ),
         )
         .await?;
+        db.execute(
+            builder.build(
+                schema
+                    .create_table_from_entity(update_state::Entity)
+                    .if_not_exists(),
+            ),
+        )
+        .await?;
+        db.execute(
+            builder.build(
+                schema
+                    .create_table_from_entity(chat_event::Entity)
+                    .if_not_exists(),
+            ),
+        )
+        .await?;
+        db.execute(
+            builder.build(
+                schema
+                    .create_table_from_entity(chat_edge::Entity)
+                    .if_not_exists(),
+            ),
+        )
+        .await?;
+        db.execute(
+            builder.build(
+                schema
+                    .create_table_from_entity(forward_candidate::Entity)
+                    .if_not_exists(),
+            ),
+        )
+        .await?;
+        db.execute(
+            builder.build(
+                schema
+                    .create_table_from_entity(user::Entity)
+                    .if_not_exists(),
+            ),
+        )
+        .await?;
+        db.execute(
+            builder.build(
+                schema
+                    .create_table_from_entity(username_history::Entity)
+                    .if_not_exists(),
+            ),
+        )
+        .await?;
+        db.execute(
+            builder.build(
+                schema
+                    .create_table_from_entity(alert::Entity)
+                    .if_not_exists(),
+            ),
+        )
+        .await?;
+        db.execute(
+            builder.build(
+                schema
+                    .create_table_from_entity(search_result::Entity)
+                    .if_not_exists(),
+            ),
+        )
+        .await?;
+
+        for sql in MIGRATIONS {
+            db.execute_unprepared(sql).await?;
+        }
+        let search_mode = fulltext::setup(&db).await?;
 
-        Ok(Self { db })
+        Ok(Self { db, search_mode })
     }
 
-    pub async fn put_message(&self, data: message::ActiveModel) -> Result<message::Model> {
+    /// 写入消息，已存在时以新内容覆盖可被编辑的列，返回写入后的行及其是否为新插入
+    pub async fn put_message(&self, data: message::ActiveModel) -> Result<(message::Model, bool)> {
+        let _timer = metrics::DB_LATENCY
+            .with_label_values(&["put_message"])
+            .start_timer();
         let (chat_id, msg_id) = if let (Some(chat_id), Some(msg_id)) =
             (data.chat_id.clone().take(), data.msg_id.clone().take())
         {
@@ -72,22 +217,56 @@ impl Database {
             bail!("put_message方法未提供chat_id与msg_id")
         };
 
-        let trans = self.db.begin().await?;
-        let _ = message::Entity::insert(data)
-            .on_conflict_do_nothing()
-            .exec(&trans)
-            .await?;
+        let mut stmt = message::Entity::insert(data)
+            .on_conflict(
+                OnConflict::columns([message::Column::ChatId, message::Column::MsgId])
+                    .update_columns([
+                        message::Column::Raw,
+                        message::Column::Text,
+                        message::Column::EditDate,
+                        message::Column::Views,
+                        message::Column::Forwards,
+                        message::Column::MediaType,
+                    ])
+                    .to_owned(),
+            )
+            .build(DbBackend::Postgres);
+        // xmax为0说明是新插入的行，冲突更新的行xmax为当前事务号
+        stmt.sql
+            .push_str(r#" RETURNING *, (xmax = 0) AS "inserted""#);
 
-        let ret = message::Entity::find_by_id((chat_id, msg_id))
-            .one(&trans)
-            .await?
-            .expect("事务进行中");
+        let Some(row) = self.db.query_one(stmt).await? else {
+            bail!("写入消息{chat_id}/{msg_id}未返回记录");
+        };
+        let ret = message::Model::from_query_result(&row, "")?;
+        let inserted: bool = row.try_get("", "inserted")?;
+        Ok((ret, inserted))
+    }
 
-        trans.commit().await?;
+    /// 批量写入消息，已存在的`(chat_id, msg_id)`忽略，返回新写入的条数
+    pub async fn put_messages(&self, data: Vec<message::ActiveModel>) -> Result<u64> {
+        let _timer = metrics::DB_LATENCY
+            .with_label_values(&["put_messages"])
+            .start_timer();
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let ret = message::Entity::insert_many(data)
+            .on_conflict(
+                OnConflict::columns([message::Column::ChatId, message::Column::MsgId])
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .exec_without_returning(&self.db)
+            .await?;
         Ok(ret)
     }
 
-    pub async fn put_chat(&self, data: chat::ActiveModel) -> Result<chat::Model> {
+    /// 写入聊天，已存在时不更新，返回存储的行及其是否为新插入
+    pub async fn put_chat(&self, data: chat::ActiveModel) -> Result<(chat::Model, bool)> {
+        let _timer = metrics::DB_LATENCY
+            .with_label_values(&["put_chat"])
+            .start_timer();
         let chat_id = if let Some(chat_id) = data.chat_id.clone().take() {
             chat_id
         } else {
@@ -98,7 +277,7 @@ impl Database {
             .one(&self.db)
             .await?;
         if let Some(exist) = exist {
-            Ok(exist)
+            Ok((exist, false))
         } else {
             let ret = chat::Entity::insert(data)
                 .on_conflict(
@@ -108,11 +287,39 @@ impl Database {
                 )
                 .exec_with_returning(&self.db)
                 .await?;
-            Ok(ret)
+            Ok((ret, true))
         }
     }
 
+    /// 写入聊天，已存在时更新`packed`、`usernames`与`name`
+    pub async fn refresh_chat(&self, data: chat::ActiveModel) -> Result<chat::Model> {
+        let _timer = metrics::DB_LATENCY
+            .with_label_values(&["refresh_chat"])
+            .start_timer();
+        let ret = chat::Entity::insert(data)
+            .on_conflict(
+                OnConflict::column(chat::Column::ChatId)
+                    .update_columns([
+                        chat::Column::Packed,
+                        chat::Column::Usernames,
+                        chat::Column::Name,
+                    ])
+                    .to_owned(),
+            )
+            .exec_with_returning(&self.db)
+            .await?;
+        Ok(ret)
+    }
+
     pub async fn put_link(&self, data: link::ActiveModel) -> Result<link::Model> {
+        Ok(self.put_link_checked(data).await?.0)
+    }
+
+    /// 写入链接，同时返回此前是否不存在
+    pub async fn put_link_checked(&self, data: link::ActiveModel) -> Result<(link::Model, bool)> {
+        let _timer = metrics::DB_LATENCY
+            .with_label_values(&["put_link"])
+            .start_timer();
         let link = if let Some(link) = data.link.clone().take() {
             link
         } else {
@@ -123,7 +330,7 @@ impl Database {
             .one(&self.db)
             .await?;
         if let Some(exist) = exist {
-            Ok(exist)
+            Ok((exist, false))
         } else {
             let ret = link::Entity::insert(data)
                 .on_conflict(
@@ -133,15 +340,154 @@ impl Database {
                 )
                 .exec_with_returning(&self.db)
                 .await?;
-            Ok(ret)
+            Ok((ret, true))
+        }
+    }
+
+    /// 写入消息中提取的链接，返回写入或已存在的链接
+    pub async fn put_links(
+        &self,
+        links: Vec<link::Link>,
+        source: &Source,
+    ) -> Result<Vec<link::Model>> {
+        let mut ret = Vec::with_capacity(links.len());
+        for link in links {
+            ret.push(self.put_link(link.to_model(source)).await?);
         }
+        Ok(ret)
     }
 
     pub async fn put_search(&self, data: search::ActiveModel) -> Result<search::Model> {
+        let _timer = metrics::DB_LATENCY
+            .with_label_values(&["put_search"])
+            .start_timer();
         let ret = data.insert(&self.db).await?;
         Ok(ret)
     }
 
+    /// 更新搜索的翻页进度
+    pub async fn update_search(&self, data: search::ActiveModel) -> Result<search::Model> {
+        let _timer = metrics::DB_LATENCY
+            .with_label_values(&["update_search"])
+            .start_timer();
+        let ret = data.update(&self.db).await?;
+        Ok(ret)
+    }
+
+    pub async fn put_chat_event(&self, data: chat_event::ActiveModel) -> Result<chat_event::Model> {
+        let _timer = metrics::DB_LATENCY
+            .with_label_values(&["put_chat_event"])
+            .start_timer();
+        let ret = data.insert(&self.db).await?;
+        Ok(ret)
+    }
+
+    /// 写入用户，用户名或姓名变化时记录历史
+    ///
+    /// `min`用户只携带部分字段，已存在时只更新`last_seen`，也不记录历史。
+    /// 返回写入后的行及其是否为新插入
+    pub async fn put_user(
+        &self,
+        data: user::ActiveModel,
+        min: bool,
+    ) -> Result<(user::Model, bool)> {
+        let _timer = metrics::DB_LATENCY
+            .with_label_values(&["put_user"])
+            .start_timer();
+        let data = data.try_into_model()?;
+        let user_id = data.user_id;
+
+        let update = if min {
+            r#""last_seen" = EXCLUDED."last_seen""#
+        } else {
+            r#""usernames" = EXCLUDED."usernames", "first_name" = EXCLUDED."first_name",
+                "last_name" = EXCLUDED."last_name", "is_bot" = EXCLUDED."is_bot",
+                "is_scam" = EXCLUDED."is_scam", "is_fake" = EXCLUDED."is_fake",
+                "premium" = EXCLUDED."premium", "phone" = EXCLUDED."phone",
+                "last_seen" = EXCLUDED."last_seen""#
+        };
+        // 同一语句内的"old"读取的是写入前的行，xmax为0说明是新插入的行
+        let raw_sql = Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            format!(
+                r#"WITH "old" AS (
+                    SELECT "usernames", "first_name", "last_name" FROM "user" WHERE "user_id" = $1
+                )
+                INSERT INTO "user" ("user_id", "usernames", "first_name", "last_name", "is_bot",
+                    "is_scam", "is_fake", "premium", "phone", "first_seen", "last_seen")
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+                ON CONFLICT ("user_id") DO UPDATE SET {update}
+                RETURNING "user".*, (xmax = 0) AS "inserted",
+                    (SELECT "usernames" FROM "old") AS "old_usernames",
+                    EXISTS (
+                        SELECT 1 FROM "old"
+                        WHERE ("old"."usernames", "old"."first_name", "old"."last_name")
+                        IS DISTINCT FROM ("user"."usernames", "user"."first_name", "user"."last_name")
+                    ) AS "renamed""#
+            ),
+            [
+                data.user_id.into(),
+                data.usernames.into(),
+                data.first_name.into(),
+                data.last_name.into(),
+                data.is_bot.into(),
+                data.is_scam.into(),
+                data.is_fake.into(),
+                data.premium.into(),
+                data.phone.into(),
+                data.first_seen.into(),
+                data.last_seen.into(),
+            ],
+        );
+
+        let trans = self.db.begin().await?;
+        let Some(row) = trans.query_one(raw_sql).await? else {
+            bail!("写入用户{user_id}未返回记录");
+        };
+        let ret = user::Model::from_query_result(&row, "")?;
+        let inserted: bool = row.try_get("", "inserted")?;
+        let renamed: bool = row.try_get("", "renamed")?;
+        if renamed && !inserted {
+            let old: Option<Vec<String>> = row.try_get("", "old_usernames")?;
+            info!(user_id, ?old, new = ?ret.usernames, "用户改名");
+        }
+        if !min && (inserted || renamed) {
+            username_history::ActiveModel::from_user(&ret)
+                .insert(&trans)
+                .await?;
+        }
+        trans.commit().await?;
+        Ok((ret, inserted))
+    }
+
+    /// 按用户名查找用户，包括曾用名
+    pub async fn find_users_by_username(&self, username: &str) -> Result<Vec<user::Model>> {
+        let raw_sql = Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            r#"SELECT * FROM "user" WHERE "user_id" IN (
+                SELECT "user_id" FROM "username_history" WHERE $1 = ANY("usernames")
+            )"#,
+            [username.into()],
+        );
+        let ret = user::Entity::find()
+            .from_raw_sql(raw_sql)
+            .all(&self.db)
+            .await?;
+        Ok(ret)
+    }
+
+    pub async fn find_username_history(
+        &self,
+        user_id: i64,
+    ) -> Result<Vec<username_history::Model>> {
+        let ret = username_history::Entity::find()
+            .filter(username_history::Column::UserId.eq(user_id))
+            .order_by(username_history::Column::Seen, Order::Asc)
+            .all(&self.db)
+            .await?;
+        Ok(ret)
+    }
+
     pub async fn find_chat(&self, username: Option<&str>) -> Result<Option<chat::Model>> {
         if username.is_none() {
             return Ok(None);
@@ -161,6 +507,111 @@ impl Database {
         Ok(ret)
     }
 
+    /// 投票所在的消息，同一投票被转发到多个聊天时优先取原始消息
+    pub async fn find_poll_message(&self, poll_id: i64) -> Result<Option<message::Model>> {
+        let raw_sql = Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            r#"SELECT * FROM "message"
+                WHERE "media_type" = 'poll'
+                AND ("raw" #>> '{media,Poll,poll,Poll,id}')::bigint = $1
+                ORDER BY "fwd_from_chat" IS NOT NULL, "date"
+                LIMIT 1"#,
+            [poll_id.into()],
+        );
+        let ret = message::Entity::find()
+            .from_raw_sql(raw_sql)
+            .one(&self.db)
+            .await?;
+        Ok(ret)
+    }
+
+    /// 更新已存储消息的浏览量，只增不减，未更新时返回None
+    pub async fn set_message_views(
+        &self,
+        chat_id: i64,
+        msg_id: i32,
+        views: i32,
+    ) -> Result<Option<message::Model>> {
+        let _timer = metrics::DB_LATENCY
+            .with_label_values(&["set_message_views"])
+            .start_timer();
+        let ret = message::Entity::update_many()
+            .col_expr(message::Column::Views, Expr::value(views))
+            .filter(message::Column::ChatId.eq(chat_id))
+            .filter(message::Column::MsgId.eq(msg_id))
+            .filter(
+                Condition::any()
+                    .add(message::Column::Views.is_null())
+                    .add(message::Column::Views.lt(views)),
+            )
+            .exec_with_returning(&self.db)
+            .await?;
+        Ok(ret.into_iter().next())
+    }
+
+    /// 聊天中已存储的最大消息编号
+    pub async fn find_last_msg_id(&self, chat_id: i64) -> Result<Option<i32>> {
+        let ret = message::Entity::find()
+            .filter(message::Column::ChatId.eq(chat_id))
+            .order_by(message::Column::MsgId, Order::Desc)
+            .one(&self.db)
+            .await?
+            .map(|m| m.msg_id);
+        Ok(ret)
+    }
+
+    /// 为旧版本写入的消息由raw列补全规范化列，返回更新的行数
+    pub async fn backfill_message_columns(&self, batch: u64) -> Result<u64> {
+        let mut cursor: Option<(i64, i32)> = None;
+        let mut updated = 0;
+        loop {
+            let mut query = message::Entity::find().filter(message::Column::Date.is_null());
+            if let Some((chat_id, msg_id)) = cursor {
+                query = query.filter(
+                    Condition::any()
+                        .add(message::Column::ChatId.gt(chat_id))
+                        .add(
+                            Condition::all()
+                                .add(message::Column::ChatId.eq(chat_id))
+                                .add(message::Column::MsgId.gt(msg_id)),
+                        ),
+                );
+            }
+            let rows = query
+                .order_by(message::Column::ChatId, Order::Asc)
+                .order_by(message::Column::MsgId, Order::Asc)
+                .limit(batch)
+                .all(&self.db)
+                .await?;
+            let Some(last) = rows.last() else {
+                break;
+            };
+            cursor = Some((last.chat_id, last.msg_id));
+
+            let trans = self.db.begin().await?;
+            for row in rows {
+                let columns = match message::Columns::from_json(&row.raw) {
+                    Ok(columns) => columns,
+                    Err(e) => {
+                        warn!(
+                            chat_id = row.chat_id,
+                            msg_id = row.msg_id,
+                            "无法解析消息raw列 >> {e}"
+                        );
+                        continue;
+                    }
+                };
+                let mut model = row.into_active_model();
+                columns.apply(&mut model);
+                model.update(&trans).await?;
+                updated += 1;
+            }
+            trans.commit().await?;
+            info!(updated, "补全消息列");
+        }
+        Ok(updated)
+    }
+
     pub async fn set_link_extracted(
         &self,
         link_id: i32,
@@ -198,6 +649,16 @@ impl Database {
         Ok(ret)
     }
 
+    /// 已加入的群组与频道
+    pub async fn find_joined_groups(&self) -> Result<Vec<chat::Model>> {
+        let ret = chat::Entity::find()
+            .filter(chat::Column::Joined.eq(true))
+            .filter(chat::Column::Ty.ne(chat::ChatType::User))
+            .all(&self.db)
+            .await?;
+        Ok(ret)
+    }
+
     pub async fn find_oldest_joined(&self) -> Result<Option<chat::Model>> {
         let ret = chat::Entity::find()
             .filter(chat::Column::Joined.eq(true))
@@ -248,22 +709,108 @@ impl Database {
         }
     }
 
-    pub async fn sync_chat_joined(&self, ctx: Context) -> Result<()> {
+    pub async fn load_update_state(&self) -> Result<Option<UpdateState>> {
+        let models = update_state::Entity::find().all(&self.db).await?;
+        Ok(update_state::to_state(models))
+    }
+
+    pub async fn save_update_state(&self, state: &UpdateState) -> Result<()> {
+        let _timer = metrics::DB_LATENCY
+            .with_label_values(&["save_update_state"])
+            .start_timer();
+        update_state::Entity::insert_many(update_state::ActiveModel::from_state(state))
+            .on_conflict(
+                OnConflict::column(update_state::Column::ChannelId)
+                    .update_columns([
+                        update_state::Column::Pts,
+                        update_state::Column::Qts,
+                        update_state::Column::Date,
+                        update_state::Column::Seq,
+                        update_state::Column::Updated,
+                    ])
+                    .to_owned(),
+            )
+            .exec(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// 以对话列表为准更新加入状态，补录未记录的聊天，返回对话数
+    pub async fn sync_chat_joined(&self, ctx: Context) -> Result<usize> {
         let trans = self.db.begin().await?;
         chat::Entity::update_many()
             .col_expr(chat::Column::Joined, Expr::value(false))
             .exec(&trans)
             .await?;
+        let mut count = 0;
         let mut chats = ctx.client.iter_dialogs();
         while let Some(chat) = chats.next().await? {
+            count += 1;
             let chat_id = chat.chat.id();
-            let exist = chat::Entity::find_by_id(chat_id).one(&self.db).await?;
+            let exist = chat::Entity::find_by_id(chat_id).one(&trans).await?;
             if let Some(exist) = exist {
                 let mut model = exist.into_active_model();
                 model.joined = Set(true);
                 model.update(&trans).await?;
+            } else {
+                info!(chat_id, chat_name = chat.chat.name(), "补录对话");
+                let source = Source::new(SourceType::None, 0, None);
+                chat::ActiveModel::from_chat(&chat.chat, true, source)
+                    .insert(&trans)
+                    .await?;
             }
         }
-        Ok(())
+        trans.commit().await?;
+        Ok(count)
+    }
+
+    pub async fn stats(&self) -> Result<Stats> {
+        Ok(Stats {
+            chats: chat::Entity::find().count(&self.db).await?,
+            joined_chats: chat::Entity::find()
+                .filter(chat::Column::Joined.eq(true))
+                .count(&self.db)
+                .await?,
+            links: link::Entity::find().count(&self.db).await?,
+            unparsed_links: link::Entity::find()
+                .filter(link::Column::Parsed.eq(false))
+                .count(&self.db)
+                .await?,
+            messages: message::Entity::find().count(&self.db).await?,
+            searches: search::Entity::find().count(&self.db).await?,
+            users: user::Entity::find().count(&self.db).await?,
+            chat_edges: chat_edge::Entity::find().count(&self.db).await?,
+            forward_candidates: forward_candidate::Entity::find()
+                .filter(forward_candidate::Column::Parsed.eq(false))
+                .count(&self.db)
+                .await?,
+        })
+    }
+}
+
+/// 各表的行数
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    pub chats: u64,
+    pub joined_chats: u64,
+    pub links: u64,
+    pub unparsed_links: u64,
+    pub messages: u64,
+    pub searches: u64,
+    pub users: u64,
+    pub chat_edges: u64,
+    /// 待解析的转发来源
+    pub forward_candidates: u64,
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "聊天\t{}（已加入{}）", self.chats, self.joined_chats)?;
+        writeln!(f, "链接\t{}（待解析{}）", self.links, self.unparsed_links)?;
+        writeln!(f, "消息\t{}", self.messages)?;
+        writeln!(f, "搜索\t{}", self.searches)?;
+        writeln!(f, "用户\t{}", self.users)?;
+        writeln!(f, "聊天关系\t{}", self.chat_edges)?;
+        write!(f, "待解析转发来源\t{}", self.forward_candidates)
     }
 }
@@ -0,0 +1,110 @@
+//! 告警投递记录
+
+use anyhow::Result;
+use chrono::Utc;
+use sea_orm::{
+    prelude::*, sea_query::OnConflict, ActiveValue::NotSet, IntoActiveModel, QueryOrder, Set,
+    TransactionTrait,
+};
+
+use super::Database;
+use crate::{
+    metrics,
+    types::alert::{self, AlertStatus},
+};
+
+impl Database {
+    /// 记录一次命中，同一规则、webhook与消息已记录过时返回`None`
+    ///
+    /// 只有确实写入新记录时才调用`status`决定其状态，事务提交前其他连接看不到此记录
+    pub async fn put_alert(
+        &self,
+        rule: &str,
+        webhook: &str,
+        (chat_id, msg_id): (i64, i32),
+        payload: Json,
+        status: impl FnOnce() -> AlertStatus,
+    ) -> Result<Option<alert::Model>> {
+        let _timer = metrics::DB_LATENCY
+            .with_label_values(&["put_alert"])
+            .start_timer();
+        let data = alert::ActiveModel {
+            id: NotSet,
+            rule: Set(rule.to_string()),
+            webhook: Set(webhook.to_string()),
+            chat_id: Set(chat_id),
+            msg_id: Set(msg_id),
+            status: Set(AlertStatus::Pending),
+            attempts: Set(0),
+            error: Set(None),
+            payload: Set(payload),
+            created: Set(Utc::now().naive_utc()),
+            delivered: Set(None),
+        };
+        let trans = self.db.begin().await?;
+        let ret = alert::Entity::insert(data)
+            .on_conflict(
+                OnConflict::columns([
+                    alert::Column::Rule,
+                    alert::Column::Webhook,
+                    alert::Column::ChatId,
+                    alert::Column::MsgId,
+                ])
+                .do_nothing()
+                .to_owned(),
+            )
+            .exec_with_returning(&trans)
+            .await;
+        let mut model = match ret {
+            Ok(model) => model,
+            Err(DbErr::RecordNotInserted | DbErr::RecordNotFound(_)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let status = status();
+        if status != AlertStatus::Pending {
+            let mut data = model.into_active_model();
+            data.status = Set(status);
+            model = data.update(&trans).await?;
+        }
+        trans.commit().await?;
+        Ok(Some(model))
+    }
+
+    /// 待投递的告警，按写入顺序
+    pub async fn find_pending_alerts(&self) -> Result<Vec<alert::Model>> {
+        let ret = alert::Entity::find()
+            .filter(alert::Column::Status.eq(AlertStatus::Pending))
+            .order_by_asc(alert::Column::Id)
+            .all(&self.db)
+            .await?;
+        Ok(ret)
+    }
+
+    /// 记录一次投递尝试的结果
+    pub async fn set_alert_attempt(
+        &self,
+        id: i64,
+        attempts: i32,
+        status: AlertStatus,
+        error: Option<String>,
+    ) -> Result<()> {
+        let _timer = metrics::DB_LATENCY
+            .with_label_values(&["set_alert_attempt"])
+            .start_timer();
+        let delivered = match status {
+            AlertStatus::Delivered => Set(Some(Utc::now().naive_utc())),
+            _ => NotSet,
+        };
+        alert::ActiveModel {
+            id: Set(id),
+            attempts: Set(attempts),
+            status: Set(status),
+            error: Set(error),
+            delivered,
+            ..Default::default()
+        }
+        .update(&self.db)
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+//! 消息全文搜索
+//!
+//! 优先使用zhparser中文分词的全文索引，数据库未安装zhparser时退回pg_trgm三元组索引，
+//! 两者都不可用时只做不区分大小写的子串匹配。
+
+use anyhow::Result;
+use sea_orm::{prelude::*, ConnectionTrait, DbBackend, FromQueryResult, Statement, Value};
+use tracing::{info, warn};
+
+use super::Database;
+use crate::{metrics, PrintError};
+
+/// 三元组索引，支持任意子串匹配
+const TRIGRAM: [&str; 2] = [
+    "CREATE EXTENSION IF NOT EXISTS pg_trgm",
+    r#"CREATE INDEX IF NOT EXISTS "message_text_trgm" ON "message" USING gin ("text" gin_trgm_ops)"#,
+];
+
+/// zhparser中文分词配置与全文索引
+const ZHPARSER: [&str; 3] = [
+    "CREATE EXTENSION IF NOT EXISTS zhparser",
+    r#"DO $$ BEGIN
+        IF NOT EXISTS (SELECT 1 FROM pg_ts_config WHERE cfgname = 'chinese') THEN
+            CREATE TEXT SEARCH CONFIGURATION chinese (PARSER = zhparser);
+            ALTER TEXT SEARCH CONFIGURATION chinese ADD MAPPING FOR n,v,a,i,e,l WITH simple;
+        END IF;
+    END $$"#,
+    r#"CREATE INDEX IF NOT EXISTS "message_text_fts" ON "message" USING gin (to_tsvector('chinese', coalesce("text", '')))"#,
+];
+
+/// 消息全文搜索的匹配方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchMode {
+    /// zhparser中文分词全文索引
+    Fulltext,
+    /// pg_trgm三元组索引，按相似度排序
+    Trigram,
+    /// 无索引的子串匹配，不排序
+    Substring,
+}
+
+/// 高亮片段的起止标记
+pub const HIGHLIGHT_START: &str = "<b>";
+pub const HIGHLIGHT_STOP: &str = "</b>";
+
+#[derive(Debug, Clone)]
+pub struct MessageQuery {
+    /// 为空时搜索全部聊天
+    pub chat_ids: Vec<i64>,
+    pub sender_id: Option<i64>,
+    pub since: Option<DateTime>,
+    pub until: Option<DateTime>,
+    /// 从0开始
+    pub page: u64,
+    pub page_size: u64,
+}
+
+impl Default for MessageQuery {
+    fn default() -> Self {
+        Self {
+            chat_ids: Vec::new(),
+            sender_id: None,
+            since: None,
+            until: None,
+            page: 0,
+            page_size: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, FromQueryResult)]
+pub struct MessageHit {
+    pub chat_id: i64,
+    pub msg_id: i32,
+    pub date: Option<DateTime>,
+    pub sender_id: Option<i64>,
+    pub text: Option<String>,
+    /// 以[`HIGHLIGHT_START`]与[`HIGHLIGHT_STOP`]标记命中部分的文本
+    pub highlight: Option<String>,
+    pub rank: f32,
+}
+
+/// 按数据库已安装的扩展建立索引，返回可用的匹配方式
+pub(super) async fn setup(db: &DatabaseConnection) -> Result<SearchMode> {
+    let mut mode = SearchMode::Trigram;
+    for sql in TRIGRAM {
+        if db.execute_unprepared(sql).await.ok_or_warn().is_none() {
+            warn!("未能启用pg_trgm三元组索引");
+            mode = SearchMode::Substring;
+            break;
+        }
+    }
+    for sql in ZHPARSER {
+        if db.execute_unprepared(sql).await.ok_or_warn().is_none() {
+            warn!(?mode, "未能启用zhparser中文分词，全文搜索退回");
+            return Ok(mode);
+        }
+    }
+    info!("已启用zhparser中文分词全文搜索");
+    Ok(SearchMode::Fulltext)
+}
+
+/// 转义正则中的特殊字符，使关键词按字面匹配
+///
+/// 只转义ASCII标点，PostgreSQL正则中反斜杠加字母数字是转义序列
+fn escape_regex(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c.is_ascii_punctuation() {
+            ret.push('\\');
+        }
+        ret.push(c);
+    }
+    ret
+}
+
+/// 转义LIKE模式中的通配符，使关键词按字面匹配
+pub fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+impl Database {
+    /// 搜索消息文本，按相关度与时间排序，分页返回
+    pub async fn search_messages(
+        &self,
+        query: &str,
+        filter: &MessageQuery,
+    ) -> Result<Vec<MessageHit>> {
+        let _timer = metrics::DB_LATENCY
+            .with_label_values(&["search_messages"])
+            .start_timer();
+
+        let mut values: Vec<Value> = vec![query.into()];
+        let (select, mut conds) = if self.search_mode == SearchMode::Fulltext {
+            (
+                format!(
+                    r#"SELECT "chat_id", "msg_id", "date", "sender_id", "text",
+                        ts_headline('chinese', coalesce("text", ''), q,
+                            'StartSel={HIGHLIGHT_START}, StopSel={HIGHLIGHT_STOP}, MaxFragments=3') AS "highlight",
+                        ts_rank(to_tsvector('chinese', coalesce("text", '')), q) AS "rank"
+                    FROM "message", websearch_to_tsquery('chinese', $1) q"#
+                ),
+                vec![r#"to_tsvector('chinese', coalesce("text", '')) @@ q"#.to_string()],
+            )
+        } else {
+            values.push(format!("%{}%", escape_like(query)).into());
+            values.push(escape_regex(query).into());
+            let rank = match self.search_mode {
+                SearchMode::Trigram => r#"similarity("text", $1)"#,
+                _ => "0::real",
+            };
+            (
+                // 与ILIKE一致，高亮不区分大小写，保留原文大小写
+                format!(
+                    r#"SELECT "chat_id", "msg_id", "date", "sender_id", "text",
+                        regexp_replace("text", $3, '{HIGHLIGHT_START}\&{HIGHLIGHT_STOP}', 'gi') AS "highlight",
+                        {rank} AS "rank"
+                    FROM "message""#
+                ),
+                vec![r#""text" ILIKE $2"#.to_string()],
+            )
+        };
+
+        if !filter.chat_ids.is_empty() {
+            values.push(filter.chat_ids.clone().into());
+            conds.push(format!(r#""chat_id" = ANY(${})"#, values.len()));
+        }
+        if let Some(sender_id) = filter.sender_id {
+            values.push(sender_id.into());
+            conds.push(format!(r#""sender_id" = ${}"#, values.len()));
+        }
+        if let Some(since) = filter.since {
+            values.push(since.into());
+            conds.push(format!(r#""date" >= ${}"#, values.len()));
+        }
+        if let Some(until) = filter.until {
+            values.push(until.into());
+            conds.push(format!(r#""date" < ${}"#, values.len()));
+        }
+
+        values.push((filter.page_size as i64).into());
+        let limit = values.len();
+        values.push(((filter.page * filter.page_size) as i64).into());
+        let offset = values.len();
+
+        let sql = format!(
+            r#"{select} WHERE {} ORDER BY "rank" DESC, "date" DESC NULLS LAST LIMIT ${limit} OFFSET ${offset}"#,
+            conds.join(" AND ")
+        );
+        let ret = MessageHit::find_by_statement(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            sql,
+            values,
+        ))
+        .all(&self.db)
+        .await?;
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_like, escape_regex};
+
+    #[test]
+    fn escape_like_wildcards() {
+        assert_eq!(escape_like("plain"), "plain");
+        assert_eq!(escape_like("100%"), "100\\%");
+        assert_eq!(escape_like("a_b"), "a\\_b");
+        assert_eq!(escape_like("%_%"), "\\%\\_\\%");
+    }
+
+    #[test]
+    fn escape_like_backslash_first() {
+        // 反斜杠先转义，避免把新加的转义符再次转义
+        assert_eq!(escape_like("a\\b"), "a\\\\b");
+        assert_eq!(escape_like("\\%"), "\\\\\\%");
+    }
+
+    #[test]
+    fn escape_regex_punctuation_only() {
+        assert_eq!(escape_regex("USDT"), "USDT");
+        assert_eq!(escape_regex("a.b*c"), "a\\.b\\*c");
+        assert_eq!(escape_regex("(园区)"), "\\(园区\\)");
+        assert_eq!(escape_regex("a\\b"), "a\\\\b");
+    }
+}
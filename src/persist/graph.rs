@@ -0,0 +1,148 @@
+//! 聊天关系网络
+
+use anyhow::Result;
+use chrono::Utc;
+use grammers_client::types::PackedChat;
+use sea_orm::{
+    prelude::*, sea_query::OnConflict, Condition, DbBackend, IntoActiveModel, Order, QueryOrder,
+    Set, Statement,
+};
+
+use super::Database;
+use crate::{
+    metrics,
+    types::{
+        chat_edge::{self, ChatEdgeKind},
+        forward_candidate,
+    },
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct EdgeQuery {
+    /// 任一端为此聊天
+    pub chat_id: Option<i64>,
+    /// 为空时不限类型
+    pub kinds: Vec<ChatEdgeKind>,
+    pub min_count: i64,
+}
+
+impl Database {
+    /// 记录一次聊天间关系，已存在时累加次数
+    pub async fn put_chat_edge(
+        &self,
+        from_chat: i64,
+        to_chat: i64,
+        kind: ChatEdgeKind,
+    ) -> Result<()> {
+        let _timer = metrics::DB_LATENCY
+            .with_label_values(&["put_chat_edge"])
+            .start_timer();
+        let raw_sql = Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            r#"INSERT INTO "chat_edge" ("from_chat", "to_chat", "kind", "count", "first_seen", "last_seen")
+                VALUES ($1, $2, $3, 1, $4, $4)
+                ON CONFLICT ("from_chat", "to_chat", "kind")
+                DO UPDATE SET "count" = "chat_edge"."count" + 1, "last_seen" = EXCLUDED."last_seen""#,
+            [
+                from_chat.into(),
+                to_chat.into(),
+                kind.into_value().into(),
+                Utc::now().naive_utc().into(),
+            ],
+        );
+        self.db.execute(raw_sql).await?;
+        Ok(())
+    }
+
+    pub async fn find_chat_edges(&self, query: &EdgeQuery) -> Result<Vec<chat_edge::Model>> {
+        let mut select =
+            chat_edge::Entity::find().filter(chat_edge::Column::Count.gte(query.min_count));
+        if let Some(chat_id) = query.chat_id {
+            select = select.filter(
+                Condition::any()
+                    .add(chat_edge::Column::FromChat.eq(chat_id))
+                    .add(chat_edge::Column::ToChat.eq(chat_id)),
+            );
+        }
+        if !query.kinds.is_empty() {
+            select = select.filter(chat_edge::Column::Kind.is_in(query.kinds.clone()));
+        }
+        let ret = select
+            .order_by(chat_edge::Column::Count, Order::Desc)
+            .all(&self.db)
+            .await?;
+        Ok(ret)
+    }
+
+    /// 以`root`为中心、不超过`depth`跳的子网络，不区分边的方向
+    pub async fn chat_network(&self, root: i64, depth: i32) -> Result<Vec<chat_edge::Model>> {
+        let raw_sql = Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            r#"WITH RECURSIVE "reach" ("chat_id", "depth") AS (
+                    SELECT $1::bigint, 0
+                    UNION
+                    SELECT CASE WHEN e."from_chat" = r."chat_id" THEN e."to_chat" ELSE e."from_chat" END, r."depth" + 1
+                    FROM "reach" r JOIN "chat_edge" e ON e."from_chat" = r."chat_id" OR e."to_chat" = r."chat_id"
+                    WHERE r."depth" < $2
+                )
+                SELECT * FROM "chat_edge"
+                WHERE "from_chat" IN (SELECT "chat_id" FROM "reach")
+                    AND "to_chat" IN (SELECT "chat_id" FROM "reach")"#,
+            [root.into(), depth.into()],
+        );
+        let ret = chat_edge::Entity::find()
+            .from_raw_sql(raw_sql)
+            .all(&self.db)
+            .await?;
+        Ok(ret)
+    }
+
+    /// 加入待解析的转发来源，已存在时忽略
+    pub async fn put_forward_candidate(&self, data: forward_candidate::ActiveModel) -> Result<()> {
+        let _timer = metrics::DB_LATENCY
+            .with_label_values(&["put_forward_candidate"])
+            .start_timer();
+        forward_candidate::Entity::insert(data)
+            .on_conflict(
+                OnConflict::column(forward_candidate::Column::ChannelId)
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .do_nothing()
+            .exec(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// 记录一次解析出错，达到`max_attempts`次后标记为已解析
+    pub async fn add_forward_attempt(&self, channel_id: i64, max_attempts: i32) -> Result<()> {
+        if let Some(exist) = forward_candidate::Entity::find_by_id(channel_id)
+            .one(&self.db)
+            .await?
+        {
+            let attempts = exist.attempts + 1;
+            let mut model = exist.into_active_model();
+            model.attempts = Set(attempts);
+            model.parsed = Set(attempts >= max_attempts);
+            model.update(&self.db).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn set_forward_extracted(
+        &self,
+        channel_id: i64,
+        packed: Option<PackedChat>,
+    ) -> Result<()> {
+        if let Some(exist) = forward_candidate::Entity::find_by_id(channel_id)
+            .one(&self.db)
+            .await?
+        {
+            let mut model = exist.into_active_model();
+            model.parsed = Set(true);
+            model.packed = Set(packed.map(|p| p.to_hex()));
+            model.update(&self.db).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,297 @@
+//! 远程持久化
+//!
+//! 记录在内存中攒批，由[`super::FlushSinks`]定时以MessagePack或JSON POST到`PERSIST_URL`。
+//! 缓冲满时整批写入暂存目录，重试仍失败的批次同样写入暂存目录，下次发送前按写入顺序补发。
+//! 暂存目录超过大小上限时删除最早的批次。
+
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::Utc;
+use dotenv_codegen::dotenv;
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use super::{Persist, Record};
+
+/// 留空为不启用
+pub const PERSIST_URL: &str = dotenv!("PERSIST_URL");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Encoding {
+    MessagePack,
+    Json,
+}
+
+impl Encoding {
+    fn content_type(&self) -> &'static str {
+        match self {
+            Encoding::MessagePack => "application/msgpack",
+            Encoding::Json => "application/json",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Encoding::MessagePack => "msgpack",
+            Encoding::Json => "json",
+        }
+    }
+
+    fn from_extension(ext: &str) -> Option<Self> {
+        match ext {
+            "msgpack" => Some(Encoding::MessagePack),
+            "json" => Some(Encoding::Json),
+            _ => None,
+        }
+    }
+
+    /// 一批记录编码为数组
+    fn encode(&self, records: &[Record]) -> Result<Bytes> {
+        let body = match self {
+            Encoding::MessagePack => rmp_serde::to_vec_named(records)?,
+            Encoding::Json => serde_json::to_vec(records)?,
+        };
+        Ok(body.into())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpSinkConfig {
+    pub encoding: Encoding,
+    /// 缓冲达到此条数时写入暂存目录，等待定时发送
+    pub batch: usize,
+    /// 定时发送的间隔秒数
+    pub flush_secs: u64,
+    /// 单批发送失败后的重试次数，间隔从1秒起翻倍
+    pub retries: u32,
+    /// 重试仍失败的批次写入此目录
+    pub spool_dir: PathBuf,
+    /// 暂存目录的大小上限（MB），超出时删除最早的批次
+    pub spool_max_mb: u64,
+}
+
+impl Default for HttpSinkConfig {
+    fn default() -> Self {
+        Self {
+            encoding: Encoding::MessagePack,
+            batch: 500,
+            flush_secs: 10,
+            retries: 3,
+            spool_dir: PathBuf::from("spool"),
+            spool_max_mb: 1024,
+        }
+    }
+}
+
+pub struct HttpSink {
+    url: String,
+    config: HttpSinkConfig,
+    client: reqwest::Client,
+    buffer: Mutex<Vec<Record>>,
+    /// 同一时间只有一批在发送，保证到达顺序
+    sending: Mutex<()>,
+    spooled: AtomicU64,
+}
+
+impl HttpSink {
+    pub fn new(url: impl Into<String>, config: HttpSinkConfig) -> Result<Self> {
+        std::fs::create_dir_all(&config.spool_dir)?;
+        // 写入临时文件时崩溃留下的批次不完整，无法补发
+        for entry in std::fs::read_dir(&config.spool_dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "tmp") {
+                warn!(path = %path.display(), "删除未写完的暂存批次");
+                std::fs::remove_file(&path)?;
+            }
+        }
+        Ok(Self {
+            url: url.into(),
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(30))
+                .build()?,
+            buffer: Mutex::new(Vec::with_capacity(config.batch)),
+            sending: Mutex::new(()),
+            spooled: AtomicU64::new(0),
+            config,
+        })
+    }
+
+    /// 未配置`PERSIST_URL`时返回`None`
+    pub fn from_dotenv(config: HttpSinkConfig) -> Result<Option<Self>> {
+        if PERSIST_URL.is_empty() {
+            return Ok(None);
+        }
+        info!(url = PERSIST_URL, "启用远程持久化");
+        Self::new(PERSIST_URL, config).map(Some)
+    }
+
+    async fn send(&self, encoding: Encoding, body: Bytes) -> Result<()> {
+        let mut failures = 0;
+        loop {
+            let ret = self
+                .client
+                .post(&self.url)
+                .header(CONTENT_TYPE, encoding.content_type())
+                .body(body.clone())
+                .send()
+                .await
+                .and_then(|resp| resp.error_for_status());
+            match ret {
+                Ok(_) => return Ok(()),
+                Err(e) if failures < self.config.retries => {
+                    failures += 1;
+                    let delay = Duration::from_secs(1 << (failures - 1).min(6));
+                    warn!(failures, ?delay, "远程持久化发送失败，等待重试 >> {e}");
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => bail!("远程持久化发送失败 url={} >> {e}", self.url),
+            }
+        }
+    }
+
+    /// 文件名以时间戳开头，按文件名排序即写入顺序
+    fn spool(&self, body: &[u8]) -> Result<PathBuf> {
+        let name = format!(
+            "{}-{:06}.{}",
+            Utc::now().timestamp_millis(),
+            self.spooled.fetch_add(1, Ordering::Relaxed),
+            self.config.encoding.extension()
+        );
+        let path = self.config.spool_dir.join(name);
+        self.evict_spool(body.len() as u64)?;
+        // 先写临时文件再改名，避免补发读到写了一半的批次
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, body)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(path)
+    }
+
+    /// 删除最早的批次，直到再写入`incoming`字节不超过大小上限
+    fn evict_spool(&self, incoming: u64) -> Result<()> {
+        let max = self.config.spool_max_mb * 1024 * 1024;
+        let mut files = Vec::new();
+        let mut total = incoming;
+        for (path, _) in self.spooled_files()? {
+            let len = std::fs::metadata(&path)?.len();
+            total += len;
+            files.push((path, len));
+        }
+        let mut evicted = 0;
+        for (path, len) in files {
+            if total <= max {
+                break;
+            }
+            std::fs::remove_file(&path)?;
+            total -= len;
+            evicted += 1;
+        }
+        if evicted > 0 {
+            warn!(
+                evicted,
+                max_mb = self.config.spool_max_mb,
+                "暂存目录超过上限，删除最早的批次"
+            );
+        }
+        Ok(())
+    }
+
+    fn spooled_files(&self) -> Result<Vec<(PathBuf, Encoding)>> {
+        let mut ret = Vec::new();
+        for entry in std::fs::read_dir(&self.config.spool_dir)? {
+            let path = entry?.path();
+            let encoding = path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .and_then(Encoding::from_extension);
+            if let Some(encoding) = encoding {
+                ret.push((path, encoding));
+            }
+        }
+        ret.sort();
+        Ok(ret)
+    }
+
+    /// 补发暂存的批次，遇到失败即停止，返回补发的批次数
+    async fn drain_spool(&self, files: Vec<(PathBuf, Encoding)>) -> Result<usize> {
+        for (i, (path, encoding)) in files.iter().enumerate() {
+            // 补发期间可能因超过上限被删除
+            let body = match std::fs::read(path) {
+                Ok(body) => body,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            if let Err(e) = self.send(*encoding, body.into()).await {
+                warn!(remain = files.len() - i, "暂存批次补发失败 >> {e}");
+                return Err(e);
+            }
+            match std::fs::remove_file(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => (),
+            }
+        }
+        if !files.is_empty() {
+            info!(count = files.len(), "暂存批次补发完成");
+        }
+        Ok(files.len())
+    }
+}
+
+#[async_trait]
+impl Persist for HttpSink {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    /// 只写入缓冲，不发送网络请求
+    async fn put(&self, record: Record) -> Result<(Record, bool)> {
+        let mut buffer = self.buffer.lock().await;
+        buffer.push(record.clone());
+        if buffer.len() < self.config.batch {
+            return Ok((record, true));
+        }
+        let records = std::mem::take(&mut *buffer);
+        let body = self.config.encoding.encode(&records)?;
+        let path = self
+            .spool(&body)
+            .map_err(|e| anyhow!("写入暂存目录失败，丢弃{}条记录 >> {e}", records.len()))?;
+        info!(count = records.len(), path = %path.display(), "缓冲已满，批次写入暂存目录");
+        Ok((record, true))
+    }
+
+    async fn flush(&self) -> Result<()> {
+        let _sending = self.sending.lock().await;
+        // 在缓冲的锁内列出暂存文件，之后缓冲满写入的批次留到下次补发
+        let (records, files) = {
+            let mut buffer = self.buffer.lock().await;
+            (std::mem::take(&mut *buffer), self.spooled_files()?)
+        };
+
+        // 暂存的批次先于新批次到达
+        let drained = self.drain_spool(files).await;
+        if records.is_empty() {
+            return drained.map(|_| ());
+        }
+        let body = self.config.encoding.encode(&records)?;
+        let sent = match drained {
+            Ok(_) => self.send(self.config.encoding, body.clone()).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = sent {
+            let path = self
+                .spool(&body)
+                .map_err(|se| anyhow!("写入暂存目录失败，丢弃{}条记录 >> {se}", records.len()))?;
+            warn!(count = records.len(), path = %path.display(), "批次写入暂存目录 >> {e}");
+        }
+        Ok(())
+    }
+}
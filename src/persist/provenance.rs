@@ -0,0 +1,117 @@
+//! 来源追溯：沿`source`/`source_id`回溯聊天是如何被发现的
+
+use anyhow::Result;
+use sea_orm::{prelude::*, DbBackend, QuerySelect, Statement};
+
+use super::Database;
+use crate::types::{chat, link, message, search, Source, SourceType};
+
+/// 防止来源记录成环时无限回溯
+const MAX_DEPTH: usize = 64;
+
+/// 来源链中的一环
+#[derive(Debug, Clone)]
+pub enum Provenance {
+    Chat(chat::Model),
+    Link(link::Model),
+    Message(message::Model),
+    /// 来源链的起点：搜索关键词
+    Search(search::Model),
+    /// 来源链的起点：手动添加
+    Manual,
+    /// 来源链的起点：由导出文件中的此聊天导入
+    Import(i64),
+    /// 来源记录已不存在，或旧版本未记录完整
+    Missing(Source),
+}
+
+impl Database {
+    /// 旧版本写入的链接只记录了来源消息编号，编号在搜索机器人聊天中唯一时补全来源聊天，
+    /// 返回更新的行数
+    pub async fn backfill_link_source_chat(&self) -> Result<u64> {
+        let raw_sql = Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            r#"UPDATE "link" SET "source_chat_id" = (
+                SELECT min("chat_id") FROM "message"
+                WHERE "message"."msg_id" = "link"."source_id" AND "message"."source" = 'search'
+                HAVING count(DISTINCT "chat_id") = 1
+            ) WHERE "source" = 'message' AND "source_chat_id" IS NULL"#,
+            [],
+        );
+        let ret = self.db.execute(raw_sql).await?;
+        Ok(ret.rows_affected())
+    }
+
+    /// 从聊天开始，依次返回其来源，直到搜索关键词或手动添加
+    pub async fn provenance(&self, chat_id: i64) -> Result<Vec<Provenance>> {
+        let mut ret = Vec::new();
+        let Some(chat) = chat::Entity::find_by_id(chat_id).one(&self.db).await? else {
+            return Ok(ret);
+        };
+        let mut source = chat.source();
+        ret.push(Provenance::Chat(chat));
+
+        while ret.len() < MAX_DEPTH {
+            let step = match source.ty {
+                SourceType::None => break,
+                SourceType::Manual => Provenance::Manual,
+                SourceType::Import => Provenance::Import(source.id),
+                SourceType::Search => search::Entity::find_by_id(source.id as i32)
+                    .one(&self.db)
+                    .await?
+                    .map(Provenance::Search)
+                    .unwrap_or(Provenance::Missing(source)),
+                SourceType::Link => link::Entity::find_by_id(source.id as i32)
+                    .one(&self.db)
+                    .await?
+                    .map(Provenance::Link)
+                    .unwrap_or(Provenance::Missing(source)),
+                SourceType::Chat => chat::Entity::find_by_id(source.id)
+                    .one(&self.db)
+                    .await?
+                    .map(Provenance::Chat)
+                    .unwrap_or(Provenance::Missing(source)),
+                SourceType::Message => self
+                    .find_source_message(source)
+                    .await?
+                    .map(Provenance::Message)
+                    .unwrap_or(Provenance::Missing(source)),
+            };
+            source = match &step {
+                Provenance::Chat(m) => m.source(),
+                Provenance::Link(m) => m.source(),
+                Provenance::Message(m) => m.source(),
+                Provenance::Search(_)
+                | Provenance::Manual
+                | Provenance::Import(_)
+                | Provenance::Missing(_) => {
+                    ret.push(step);
+                    break;
+                }
+            };
+            ret.push(step);
+        }
+        Ok(ret)
+    }
+
+    /// 旧版本的来源没有聊天编号，仅当消息编号唯一时采用
+    async fn find_source_message(&self, source: Source) -> Result<Option<message::Model>> {
+        let msg_id = source.id as i32;
+        if let Some(chat_id) = source.chat_id {
+            return Ok(message::Entity::find_by_id((chat_id, msg_id))
+                .one(&self.db)
+                .await?);
+        }
+        let mut found = message::Entity::find()
+            .filter(message::Column::MsgId.eq(msg_id))
+            .filter(message::Column::Source.eq(SourceType::Search))
+            .limit(2)
+            .all(&self.db)
+            .await?;
+        if found.len() == 1 {
+            Ok(found.pop())
+        } else {
+            Ok(None)
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! 搜索结果记录与同一关键词各次搜索的比较
+
+use std::collections::BTreeSet;
+
+use anyhow::Result;
+use sea_orm::{prelude::*, sea_query::OnConflict, QueryOrder, QuerySelect, Set};
+
+use super::Database;
+use crate::{
+    metrics,
+    types::{link, search, search_result},
+};
+
+/// 同一关键词最近两次翻到最后一页的搜索之间的差异
+#[derive(Debug, Clone)]
+pub struct SearchDiff {
+    pub previous: search::Model,
+    pub latest: search::Model,
+    /// 仅出现在最近一次搜索中
+    pub appeared: Vec<link::Model>,
+    /// 仅出现在上一次搜索中
+    pub disappeared: Vec<link::Model>,
+}
+
+impl Database {
+    /// 记录一次搜索结果中的链接，同一搜索中重复出现时忽略
+    pub async fn put_search_result(
+        &self,
+        search_id: i32,
+        link_id: i32,
+        is_new: bool,
+    ) -> Result<()> {
+        let _timer = metrics::DB_LATENCY
+            .with_label_values(&["put_search_result"])
+            .start_timer();
+        let data = search_result::ActiveModel {
+            search_id: Set(search_id),
+            link_id: Set(link_id),
+            is_new: Set(is_new),
+        };
+        search_result::Entity::insert(data)
+            .on_conflict(
+                OnConflict::columns([
+                    search_result::Column::SearchId,
+                    search_result::Column::LinkId,
+                ])
+                .do_nothing()
+                .to_owned(),
+            )
+            .do_nothing()
+            .exec(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn search_link_ids(&self, search_id: i32) -> Result<BTreeSet<i32>> {
+        let ids: Vec<i32> = search_result::Entity::find()
+            .select_only()
+            .column(search_result::Column::LinkId)
+            .filter(search_result::Column::SearchId.eq(search_id))
+            .into_tuple()
+            .all(&self.db)
+            .await?;
+        Ok(ids.into_iter().collect())
+    }
+
+    async fn find_links(&self, ids: impl IntoIterator<Item = i32>) -> Result<Vec<link::Model>> {
+        let ret = link::Entity::find()
+            .filter(link::Column::Id.is_in(ids))
+            .order_by_asc(link::Column::Id)
+            .all(&self.db)
+            .await?;
+        Ok(ret)
+    }
+
+    /// 比较同一机器人对关键词最近两次翻到最后一页的搜索，不足两次时返回`None`
+    ///
+    /// 因没有新链接而提前结束的搜索只包含部分结果，不参与比较；不同机器人的结果也不可比
+    pub async fn search_diff(&self, bot: &str, keyword: &str) -> Result<Option<SearchDiff>> {
+        let mut runs = search::Entity::find()
+            .filter(search::Column::Bot.eq(bot))
+            .filter(search::Column::Keyword.eq(keyword))
+            .filter(search::Column::Completed.eq(true))
+            .filter(search::Column::Exhaustive.eq(true))
+            .order_by_desc(search::Column::StartTime)
+            .limit(2)
+            .all(&self.db)
+            .await?;
+        let (Some(previous), Some(latest)) = (runs.pop(), runs.pop()) else {
+            return Ok(None);
+        };
+        let before = self.search_link_ids(previous.id).await?;
+        let after = self.search_link_ids(latest.id).await?;
+        let (appeared, disappeared) = diff_ids(&before, &after);
+        Ok(Some(SearchDiff {
+            appeared: self.find_links(appeared).await?,
+            disappeared: self.find_links(disappeared).await?,
+            previous,
+            latest,
+        }))
+    }
+}
+
+/// 仅在`after`中与仅在`before`中的编号
+fn diff_ids(before: &BTreeSet<i32>, after: &BTreeSet<i32>) -> (Vec<i32>, Vec<i32>) {
+    (
+        after.difference(before).copied().collect(),
+        before.difference(after).copied().collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(ids: &[i32]) -> BTreeSet<i32> {
+        ids.iter().copied().collect()
+    }
+
+    #[test]
+    fn diff_ids_splits_both_sides() {
+        let (appeared, disappeared) = diff_ids(&set(&[1, 2, 3]), &set(&[2, 3, 4, 5]));
+        assert_eq!(appeared, vec![4, 5]);
+        assert_eq!(disappeared, vec![1]);
+    }
+
+    #[test]
+    fn diff_ids_identical_or_empty() {
+        let (appeared, disappeared) = diff_ids(&set(&[1, 2]), &set(&[1, 2]));
+        assert!(appeared.is_empty() && disappeared.is_empty());
+
+        let (appeared, disappeared) = diff_ids(&set(&[]), &set(&[7]));
+        assert_eq!(appeared, vec![7]);
+        assert!(disappeared.is_empty());
+
+        let (appeared, disappeared) = diff_ids(&set(&[7]), &set(&[]));
+        assert!(appeared.is_empty());
+        assert_eq!(disappeared, vec![7]);
+    }
+}
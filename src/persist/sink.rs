@@ -0,0 +1,165 @@
+//! 持久化后端
+//!
+//! Postgres为主存储，其余后端（如[`super::HttpSink`]）接收写入主存储后的记录副本。
+//! [`Context::put`]经主存储的[`Persist`]实现写入，再将写入后的行转发给其余后端。
+
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use sea_orm::{ActiveModelTrait, ActiveValue::NotSet, IntoActiveModel};
+use serde::{Deserialize, Serialize};
+
+use super::Database;
+use crate::{
+    types::{chat, chat_event, link, message, search, user},
+    Context, Runable,
+};
+
+/// 一条写入的记录，序列化为`{"table": ..., "row": ...}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "table", content = "row", rename_all = "snake_case")]
+pub enum Record {
+    Message(message::Model),
+    Chat(chat::Model),
+    Link(link::Model),
+    Search(search::Model),
+    ChatEvent(chat_event::Model),
+    User(user::Model),
+}
+
+impl Record {
+    pub fn table(&self) -> &'static str {
+        match self {
+            Record::Message(_) => "message",
+            Record::Chat(_) => "chat",
+            Record::Link(_) => "link",
+            Record::Search(_) => "search",
+            Record::ChatEvent(_) => "chat_event",
+            Record::User(_) => "user",
+        }
+    }
+}
+
+/// 各表的行与[`Record`]互相转换，供[`Context::put`]按类型取回写入后的行
+macro_rules! record_conversions {
+    ($($variant:ident($model:ty)),* $(,)?) => {
+        $(
+            impl From<$model> for Record {
+                fn from(model: $model) -> Self {
+                    Record::$variant(model)
+                }
+            }
+
+            impl TryFrom<Record> for $model {
+                type Error = anyhow::Error;
+
+                fn try_from(record: Record) -> Result<Self> {
+                    match record {
+                        Record::$variant(model) => Ok(model),
+                        other => Err(anyhow!("记录类型不符：{}", other.table())),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+record_conversions!(
+    Message(message::Model),
+    Chat(chat::Model),
+    Link(link::Model),
+    Search(search::Model),
+    ChatEvent(chat_event::Model),
+    User(user::Model),
+);
+
+#[async_trait]
+pub trait Persist: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// 写入一条记录，返回写入后的记录及其是否为新记录；只转发记录的后端原样返回
+    async fn put(&self, record: Record) -> Result<(Record, bool)>;
+
+    /// 写出缓冲的记录，默认无缓冲
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Persist for Database {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    /// 主键为0的搜索作为新行写入，其余按主键更新；链接与聊天事件的主键由数据库分配
+    async fn put(&self, record: Record) -> Result<(Record, bool)> {
+        let ret = match record {
+            Record::Message(model) => {
+                let (stored, inserted) = self
+                    .put_message(model.into_active_model().reset_all())
+                    .await?;
+                (stored.into(), inserted)
+            }
+            Record::Chat(model) => {
+                let (stored, inserted) =
+                    self.put_chat(model.into_active_model().reset_all()).await?;
+                (stored.into(), inserted)
+            }
+            Record::Link(model) => {
+                let mut data = model.into_active_model().reset_all();
+                data.id = NotSet;
+                let (stored, created) = self.put_link_checked(data).await?;
+                (stored.into(), created)
+            }
+            Record::Search(model) if model.id == 0 => {
+                let mut data = model.into_active_model().reset_all();
+                data.id = NotSet;
+                (self.put_search(data).await?.into(), true)
+            }
+            Record::Search(model) => {
+                let stored = self
+                    .update_search(model.into_active_model().reset_all())
+                    .await?;
+                (stored.into(), false)
+            }
+            Record::ChatEvent(model) => {
+                let mut data = model.into_active_model().reset_all();
+                data.id = NotSet;
+                (self.put_chat_event(data).await?.into(), true)
+            }
+            Record::User(model) => {
+                let (stored, inserted) = self
+                    .put_user(model.into_active_model().reset_all(), false)
+                    .await?;
+                (stored.into(), inserted)
+            }
+        };
+        Ok(ret)
+    }
+}
+
+/// 定时写出[`Context::sinks`]中缓冲的记录，退出时写出剩余记录
+pub struct FlushSinks;
+
+#[async_trait]
+impl Runable for FlushSinks {
+    fn name(&self) -> &'static str {
+        "持久化后端刷新"
+    }
+
+    async fn run(&mut self, ctx: Context) -> Result<()> {
+        let secs = ctx.config.remote.flush_secs.max(1);
+        let mut tick = tokio::time::interval(Duration::from_secs(secs));
+        loop {
+            tokio::select! {
+                _ = tick.tick() => ctx.flush_sinks().await,
+                _ = ctx.cancel.cancelled() => {
+                    ctx.flush_sinks().await;
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
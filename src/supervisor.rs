@@ -0,0 +1,259 @@
+//! 任务监督者
+//!
+//! 每个[`Runable`]由[`supervise`]托管，按照[`RestartPolicy`]决定退出后是否重启，
+//! 运行状态记录在[`Supervisor`]中，可通过[`Supervisor::states`]查询。
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{NaiveDateTime, Utc};
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+
+use crate::{Context, Runable};
+
+/// 最多保留的已结束（正常退出或放弃重启）任务数，超过时移除最早添加的已结束任务
+const MAX_FINISHED: usize = 100;
+
+/// 重启时机
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Restart {
+    /// 无论是否出错都重启
+    Always,
+    /// 仅出错时重启
+    OnFailure,
+    /// 从不重启
+    Never,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RestartPolicy {
+    pub restart: Restart,
+    /// 首次重启前的等待时间，此后每次连续失败翻倍
+    pub backoff_base: Duration,
+    /// 等待时间上限，运行超过此时间视为恢复正常，重置连续失败计数
+    pub backoff_max: Duration,
+    /// 在`window`时间内最多重启的次数，超过则放弃
+    pub max_restarts: usize,
+    pub window: Duration,
+}
+
+impl RestartPolicy {
+    pub const fn never() -> Self {
+        Self {
+            restart: Restart::Never,
+            backoff_base: Duration::ZERO,
+            backoff_max: Duration::ZERO,
+            max_restarts: 0,
+            window: Duration::ZERO,
+        }
+    }
+
+    pub const fn always() -> Self {
+        Self {
+            restart: Restart::Always,
+            backoff_base: Duration::from_secs(1),
+            backoff_max: Duration::from_secs(300),
+            max_restarts: 10,
+            window: Duration::from_secs(600),
+        }
+    }
+
+    pub const fn on_failure() -> Self {
+        Self {
+            restart: Restart::OnFailure,
+            ..Self::always()
+        }
+    }
+
+    /// 第`failures`次连续失败后的等待时间
+    pub fn backoff(&self, failures: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        self.backoff_base
+            .saturating_mul(factor)
+            .min(self.backoff_max)
+    }
+
+    fn should_restart(&self, failed: bool) -> bool {
+        match self.restart {
+            Restart::Always => true,
+            Restart::OnFailure => failed,
+            Restart::Never => false,
+        }
+    }
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self::never()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    Running,
+    /// 等待重启，`until`为预计重启时间
+    BackingOff {
+        until: NaiveDateTime,
+    },
+    /// 正常退出，不再重启
+    Exited,
+    /// 出错退出或超过重启频率上限，不再重启
+    Dead,
+}
+
+#[derive(Debug, Clone)]
+pub struct TaskStatus {
+    pub name: &'static str,
+    pub policy: RestartPolicy,
+    pub state: TaskState,
+    pub restarts: usize,
+    pub started_at: NaiveDateTime,
+    pub last_error: Option<String>,
+}
+
+/// 单个任务状态的共享句柄
+#[derive(Clone)]
+pub struct TaskHandle(Arc<Mutex<TaskStatus>>);
+impl TaskHandle {
+    fn update(&self, f: impl FnOnce(&mut TaskStatus)) {
+        if let Ok(mut status) = self.0.lock() {
+            f(&mut status)
+        }
+    }
+
+    pub fn status(&self) -> Option<TaskStatus> {
+        self.0.lock().ok().map(|s| s.clone())
+    }
+}
+
+#[derive(Default)]
+pub struct Supervisor {
+    tasks: Mutex<Vec<TaskHandle>>,
+}
+impl Supervisor {
+    pub fn register(&self, name: &'static str, policy: RestartPolicy) -> TaskHandle {
+        let handle = TaskHandle(Arc::new(Mutex::new(TaskStatus {
+            name,
+            policy,
+            state: TaskState::Running,
+            restarts: 0,
+            started_at: Utc::now().naive_utc(),
+            last_error: None,
+        })));
+        if let Ok(mut tasks) = self.tasks.lock() {
+            // 短时任务会频繁结束，已结束的任务只保留最近的一部分
+            let finished = |t: &TaskHandle| {
+                t.status()
+                    .is_some_and(|s| matches!(s.state, TaskState::Exited | TaskState::Dead))
+            };
+            let mut excess = tasks
+                .iter()
+                .filter(|t| finished(t))
+                .count()
+                .saturating_sub(MAX_FINISHED);
+            tasks.retain(|t| {
+                if excess > 0 && finished(t) {
+                    excess -= 1;
+                    return false;
+                }
+                true
+            });
+            tasks.push(handle.clone());
+        }
+        handle
+    }
+
+    /// 全部任务状态的快照
+    pub fn states(&self) -> Vec<TaskStatus> {
+        self.tasks
+            .lock()
+            .map(|tasks| tasks.iter().filter_map(|t| t.status()).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// 运行任务直至其不再需要重启，或收到退出信号
+pub async fn supervise(
+    ctx: Context,
+    mut task: impl Runable,
+    policy: RestartPolicy,
+    handle: TaskHandle,
+) {
+    let name = task.name();
+    let mut restarts: VecDeque<Instant> = VecDeque::new();
+    let mut failures = 0;
+
+    loop {
+        handle.update(|s| {
+            s.state = TaskState::Running;
+            s.started_at = Utc::now().naive_utc();
+        });
+        let started = Instant::now();
+        let result = task.run(ctx.clone()).await;
+        let failed = result.is_err();
+        match result {
+            Ok(()) => info!(name, "任务退出"),
+            Err(e) => {
+                warn!(name, "任务出错退出: {}", e);
+                handle.update(|s| s.last_error = Some(e.to_string()));
+            }
+        }
+
+        let final_state = if failed {
+            TaskState::Dead
+        } else {
+            TaskState::Exited
+        };
+        if ctx.cancel.is_cancelled() || !policy.should_restart(failed) {
+            handle.update(|s| s.state = final_state);
+            return;
+        }
+
+        // 限制重启频率
+        let now = Instant::now();
+        while restarts
+            .front()
+            .is_some_and(|t| now.duration_since(*t) > policy.window)
+        {
+            restarts.pop_front();
+        }
+        if restarts.len() >= policy.max_restarts {
+            error!(
+                name,
+                max = policy.max_restarts,
+                "任务重启过于频繁，放弃重启"
+            );
+            handle.update(|s| s.state = TaskState::Dead);
+            return;
+        }
+        restarts.push_back(now);
+
+        // 指数退避
+        if started.elapsed() > policy.backoff_max {
+            failures = 0;
+        }
+        let delay = if failed {
+            failures += 1;
+            policy.backoff(failures)
+        } else {
+            policy.backoff_base
+        };
+        let until = Utc::now().naive_utc()
+            + chrono::Duration::from_std(delay).unwrap_or(chrono::Duration::zero());
+        handle.update(|s| s.state = TaskState::BackingOff { until });
+        warn!(name, delay = delay.as_secs(), "任务等待重启");
+
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => (),
+            _ = ctx.cancel.cancelled() => {
+                handle.update(|s| s.state = final_state);
+                return;
+            }
+        }
+        handle.update(|s| s.restarts += 1);
+    }
+}
@@ -0,0 +1,44 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumIter, DeriveActiveEnum)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::None)")]
+pub enum AlertStatus {
+    /// 等待投递
+    #[sea_orm(string_value = "pending")]
+    Pending,
+    #[sea_orm(string_value = "delivered")]
+    Delivered,
+    /// 重试次数用尽
+    #[sea_orm(string_value = "failed")]
+    Failed,
+    /// 超过规则的频率限制，未投递
+    #[sea_orm(string_value = "rate_limited")]
+    RateLimited,
+    /// 去重窗口内已投递过相同文本，未投递
+    #[sea_orm(string_value = "duplicate")]
+    Duplicate,
+}
+
+/// 一次规则命中对一个webhook的投递记录，`(rule, webhook, chat_id, msg_id)`唯一
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "alert")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    pub rule: String,
+    pub webhook: String,
+    pub chat_id: i64,
+    pub msg_id: i32,
+    pub status: AlertStatus,
+    pub attempts: i32,
+    pub error: Option<String>,
+    pub payload: Json,
+    pub created: DateTime,
+    pub delivered: Option<DateTime>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1,11 +1,14 @@
 use anyhow::Result;
+use chrono::Utc;
 use grammers_client::types::PackedChat;
 use sea_orm::{entity::prelude::*, FromQueryResult, Set};
 use serde::{Deserialize, Serialize};
 
 use super::{Source, SourceType};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumIter, DeriveActiveEnum)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, EnumIter, DeriveActiveEnum,
+)]
 #[sea_orm(rs_type = "String", db_type = "String(StringLen::None)")]
 pub enum ChatType {
     #[sea_orm(string_value = "user")]
@@ -26,7 +29,7 @@ impl From<&grammers_client::types::Chat> for ChatType {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
 #[sea_orm(table_name = "chat")]
 pub struct Model {
     #[sea_orm(primary_key)]
@@ -37,6 +40,7 @@ pub struct Model {
     pub packed: String,
     pub source: SourceType,
     pub source_id: i64,
+    pub source_chat_id: Option<i64>,
     pub joined: bool,
     pub last_update: DateTime,
 }
@@ -47,6 +51,7 @@ pub enum Relation {}
 impl ActiveModelBehavior for ActiveModel {}
 
 impl ActiveModel {
+    /// 新记录的`last_update`为当前时间，已存在的聊天写入时不覆盖此列
     pub fn from_chat(chat: &grammers_client::types::Chat, joined: bool, source: Source) -> Self {
         let usernames = chat
             .username()
@@ -63,13 +68,18 @@ impl ActiveModel {
             packed: Set(chat.pack().to_hex()),
             source: Set(source.ty),
             source_id: Set(source.id),
+            source_chat_id: Set(source.chat_id),
             joined: Set(joined),
-            ..Default::default()
+            last_update: Set(Utc::now().naive_utc()),
         }
     }
 }
 
 impl Model {
+    pub fn source(&self) -> Source {
+        Source::new(self.source, self.source_id, self.source_chat_id)
+    }
+
     pub fn packed(&self) -> Result<PackedChat> {
         Ok(PackedChat::from_hex(&self.packed)?)
     }
@@ -0,0 +1,41 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, EnumIter, DeriveActiveEnum,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::None)")]
+pub enum ChatEdgeKind {
+    /// `to_chat`中出现转发自`from_chat`的消息
+    #[sea_orm(string_value = "forward")]
+    Forward,
+    /// `from_chat`的消息中出现指向`to_chat`的链接
+    #[sea_orm(string_value = "link")]
+    Link,
+    /// `from_chat`的消息中@了`to_chat`
+    #[sea_orm(string_value = "mention")]
+    Mention,
+    /// 频道`from_chat`绑定的讨论组为`to_chat`
+    #[sea_orm(string_value = "linked_discussion")]
+    LinkedDiscussion,
+}
+
+/// 聊天之间的关系，同类关系重复出现时累加`count`
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "chat_edge")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub from_chat: i64,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub to_chat: i64,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub kind: ChatEdgeKind,
+    pub count: i64,
+    pub first_seen: DateTime,
+    pub last_seen: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
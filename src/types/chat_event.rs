@@ -0,0 +1,246 @@
+use chrono::{NaiveDateTime, Utc};
+use grammers_client::{grammers_tl_types as tl, types::Message as RawMessage};
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{peer_id, unix_time};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumIter, DeriveActiveEnum)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::None)")]
+pub enum ChatEventKind {
+    /// 成员加入、退出、权限变化
+    #[sea_orm(string_value = "participant")]
+    Participant,
+    #[sea_orm(string_value = "title")]
+    Title,
+    #[sea_orm(string_value = "photo")]
+    Photo,
+    #[sea_orm(string_value = "pinned")]
+    Pinned,
+    #[sea_orm(string_value = "reactions")]
+    Reactions,
+    /// 浏览量变化，写入消息的`views`列，不逐条记录
+    #[sea_orm(string_value = "views")]
+    Views,
+    /// 投票结果变化，所属聊天与消息由投票所在的消息确定
+    #[sea_orm(string_value = "poll")]
+    Poll,
+    /// 成员数跳变，由轮询发现
+    #[sea_orm(string_value = "member_count")]
+    MemberCount,
+    /// 其他服务消息
+    #[sea_orm(string_value = "service")]
+    Service,
+}
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "chat_event")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    pub chat_id: i64,
+    pub kind: ChatEventKind,
+    pub actor_id: Option<i64>,
+    pub target_id: Option<i64>,
+    pub msg_id: Option<i32>,
+    pub data: Json,
+    pub date: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+/// 由原始更新或服务消息提取的聊天事件
+#[derive(Debug, Clone)]
+pub struct ChatEvent {
+    pub chat_id: i64,
+    pub kind: ChatEventKind,
+    /// 执行操作的用户
+    pub actor_id: Option<i64>,
+    /// 被操作的用户
+    pub target_id: Option<i64>,
+    pub msg_id: Option<i32>,
+    /// 浏览量更新携带的最新浏览量
+    pub views: Option<i32>,
+    /// 投票更新只携带投票id，`chat_id`在确定投票所在的消息前为0
+    pub poll_id: Option<i64>,
+    pub data: serde_json::Value,
+    pub date: NaiveDateTime,
+}
+
+impl ChatEvent {
+    pub fn new(chat_id: i64, kind: ChatEventKind, data: serde_json::Value) -> Self {
+        Self {
+            chat_id,
+            kind,
+            actor_id: None,
+            target_id: None,
+            msg_id: None,
+            views: None,
+            poll_id: None,
+            data,
+            date: Utc::now().naive_utc(),
+        }
+    }
+
+    /// 不属于聊天事件的更新返回None
+    pub fn from_raw(update: &tl::enums::Update) -> Option<Self> {
+        let mut ret = Self::classify(update)?;
+        // 只序列化属于聊天事件的更新
+        ret.data = serde_json::to_value(update).unwrap_or_default();
+        Some(ret)
+    }
+
+    /// 聊天事件所属的聊天，不序列化原始更新
+    pub fn raw_chat_id(update: &tl::enums::Update) -> Option<i64> {
+        Self::classify(update)
+            .filter(|e| e.poll_id.is_none())
+            .map(|e| e.chat_id)
+    }
+
+    /// `data`留空
+    fn classify(update: &tl::enums::Update) -> Option<Self> {
+        use tl::enums::Update as U;
+
+        let data = serde_json::Value::Null;
+        let ret = match update {
+            U::ChannelParticipant(u) => Self {
+                actor_id: Some(u.actor_id),
+                target_id: Some(u.user_id),
+                date: unix_time(u.date),
+                ..Self::new(u.channel_id, ChatEventKind::Participant, data)
+            },
+            U::ChatParticipant(u) => Self {
+                actor_id: Some(u.actor_id),
+                target_id: Some(u.user_id),
+                date: unix_time(u.date),
+                ..Self::new(u.chat_id, ChatEventKind::Participant, data)
+            },
+            U::ChatParticipantAdd(u) => Self {
+                actor_id: Some(u.inviter_id),
+                target_id: Some(u.user_id),
+                ..Self::new(u.chat_id, ChatEventKind::Participant, data)
+            },
+            U::ChatParticipantDelete(u) => Self {
+                target_id: Some(u.user_id),
+                ..Self::new(u.chat_id, ChatEventKind::Participant, data)
+            },
+            U::ChatParticipantAdmin(u) => Self {
+                target_id: Some(u.user_id),
+                ..Self::new(u.chat_id, ChatEventKind::Participant, data)
+            },
+            U::PinnedChannelMessages(u) => Self {
+                msg_id: u.messages.first().copied(),
+                ..Self::new(u.channel_id, ChatEventKind::Pinned, data)
+            },
+            U::PinnedMessages(u) => Self {
+                msg_id: u.messages.first().copied(),
+                ..Self::new(peer_id(&u.peer), ChatEventKind::Pinned, data)
+            },
+            U::MessageReactions(u) => Self {
+                msg_id: Some(u.msg_id),
+                ..Self::new(peer_id(&u.peer), ChatEventKind::Reactions, data)
+            },
+            U::ChannelMessageViews(u) => Self {
+                msg_id: Some(u.id),
+                views: Some(u.views),
+                ..Self::new(u.channel_id, ChatEventKind::Views, data)
+            },
+            U::MessagePoll(u) => Self {
+                poll_id: Some(u.poll_id),
+                ..Self::new(0, ChatEventKind::Poll, data)
+            },
+            _ => return None,
+        };
+        Some(ret)
+    }
+
+    /// 非服务消息返回None
+    pub fn from_service(raw_msg: &RawMessage) -> Option<Self> {
+        use tl::enums::MessageAction as A;
+
+        let action = raw_msg.action()?;
+        let kind = match action {
+            A::ChatEditTitle(_) => ChatEventKind::Title,
+            A::ChatEditPhoto(_) | A::ChatDeletePhoto => ChatEventKind::Photo,
+            A::ChatAddUser(_)
+            | A::ChatDeleteUser(_)
+            | A::ChatJoinedByLink(_)
+            | A::ChatJoinedByRequest => ChatEventKind::Participant,
+            A::PinMessage => ChatEventKind::Pinned,
+            _ => ChatEventKind::Service,
+        };
+        let target_id = match action {
+            A::ChatAddUser(a) => a.users.first().copied(),
+            A::ChatDeleteUser(a) => Some(a.user_id),
+            _ => None,
+        };
+        Some(Self {
+            actor_id: raw_msg.sender().map(|s| s.id()),
+            target_id,
+            msg_id: Some(raw_msg.id()),
+            date: raw_msg.date().naive_utc(),
+            ..Self::new(
+                raw_msg.chat().id(),
+                kind,
+                serde_json::to_value(action).unwrap_or_default(),
+            )
+        })
+    }
+
+    /// 主键为0，写入时由数据库分配
+    pub fn to_model(self) -> Model {
+        Model {
+            id: 0,
+            chat_id: self.chat_id,
+            kind: self.kind,
+            actor_id: self.actor_id,
+            target_id: self.target_id,
+            msg_id: self.msg_id,
+            data: self.data,
+            date: self.date,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn views_update_carries_count() {
+        let update = tl::enums::Update::ChannelMessageViews(tl::types::UpdateChannelMessageViews {
+            channel_id: 100,
+            id: 7,
+            views: 42,
+        });
+        let event = ChatEvent::from_raw(&update).unwrap();
+        assert_eq!(event.kind, ChatEventKind::Views);
+        assert_eq!(event.chat_id, 100);
+        assert_eq!(event.msg_id, Some(7));
+        assert_eq!(event.views, Some(42));
+        assert_eq!(ChatEvent::raw_chat_id(&update), Some(100));
+    }
+
+    #[test]
+    fn poll_update_has_no_chat_until_attributed() {
+        let update = tl::enums::Update::MessagePoll(tl::types::UpdateMessagePoll {
+            poll_id: 5,
+            poll: None,
+            results: tl::enums::PollResults::Results(tl::types::PollResults {
+                min: true,
+                results: None,
+                total_voters: Some(3),
+                recent_voters: None,
+                solution: None,
+                solution_entities: None,
+            }),
+        });
+        let event = ChatEvent::from_raw(&update).unwrap();
+        assert_eq!(event.kind, ChatEventKind::Poll);
+        assert_eq!(event.poll_id, Some(5));
+        assert_eq!(ChatEvent::raw_chat_id(&update), None);
+    }
+}
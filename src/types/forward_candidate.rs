@@ -0,0 +1,54 @@
+use anyhow::Result;
+use grammers_client::types::PackedChat;
+use sea_orm::{entity::prelude::*, Set};
+
+use super::{Source, SourceType};
+
+/// 转发来源频道，待[`crate::app::extract::ScanForward`]解析加入
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "forward_candidate")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub channel_id: i64,
+    /// 包含转发消息的聊天，借此消息获取来源频道的access_hash
+    pub via_chat: String,
+    pub via_msg: i32,
+    pub source: SourceType,
+    pub source_id: i64,
+    pub source_chat_id: Option<i64>,
+    pub parsed: bool,
+    pub packed: Option<String>,
+    /// 解析出错的次数，达到上限后不再尝试
+    pub attempts: i32,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl ActiveModel {
+    pub fn new(channel_id: i64, via_chat: PackedChat, via_msg: i32, source: Source) -> Self {
+        Self {
+            channel_id: Set(channel_id),
+            via_chat: Set(via_chat.to_hex()),
+            via_msg: Set(via_msg),
+            source: Set(source.ty),
+            source_id: Set(source.id),
+            source_chat_id: Set(source.chat_id),
+            parsed: Set(false),
+            packed: Set(None),
+            attempts: Set(0),
+        }
+    }
+}
+
+impl Model {
+    pub fn source(&self) -> Source {
+        Source::new(self.source, self.source_id, self.source_chat_id)
+    }
+
+    pub fn via_chat(&self) -> Result<PackedChat> {
+        Ok(PackedChat::from_hex(&self.via_chat)?)
+    }
+}
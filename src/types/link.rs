@@ -1,10 +1,13 @@
 use std::fmt::{Display, Formatter};
 
+use grammers_client::grammers_tl_types::enums::MessageEntity;
 use sea_orm::{entity::prelude::*, ActiveValue::NotSet, Set};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
 
 use super::{Source, SourceType};
 
-#[derive(Clone, Debug, DeriveEntityModel)]
+#[derive(Clone, Debug, DeriveEntityModel, Serialize, Deserialize)]
 #[sea_orm(table_name = "link")]
 pub struct Model {
     #[sea_orm(primary_key)]
@@ -14,6 +17,7 @@ pub struct Model {
     pub desc: String,
     pub source: SourceType,
     pub source_id: i64,
+    pub source_chat_id: Option<i64>,
     pub parsed: bool,
     pub packed: Option<String>,
 }
@@ -24,6 +28,12 @@ pub enum Relation {}
 
 impl ActiveModelBehavior for ActiveModel {}
 
+impl Model {
+    pub fn source(&self) -> Source {
+        Source::new(self.source, self.source_id, self.source_chat_id)
+    }
+}
+
 impl PartialEq for Model {
     fn eq(&self, other: &Self) -> bool {
         self.link == other.link
@@ -42,6 +52,40 @@ pub struct Link {
 }
 
 impl Link {
+    /// 文本中的超链接，链接文字作为描述
+    pub fn from_entities(text: &str, entities: &[MessageEntity]) -> Vec<Self> {
+        let words: Vec<u16> = text.encode_utf16().collect();
+        let mut ret = Vec::new();
+        for ent in entities {
+            if let MessageEntity::TextUrl(url) = ent {
+                let offset = url.offset as usize;
+                let len = url.length as usize;
+                match words.get(offset..offset + len).map(String::from_utf16) {
+                    Some(Ok(desc)) => ret.push(Link {
+                        link: url.url.clone(),
+                        desc,
+                    }),
+                    _ => warn!("提取链接时错误"),
+                }
+            }
+        }
+        ret
+    }
+
+    /// 主键为0，写入时由数据库分配
+    pub fn into_model(self, source: &Source) -> Model {
+        Model {
+            id: 0,
+            link: self.link,
+            desc: self.desc,
+            source: source.ty,
+            source_id: source.id,
+            source_chat_id: source.chat_id,
+            parsed: false,
+            packed: None,
+        }
+    }
+
     pub fn to_model(self, source: &Source) -> ActiveModel {
         ActiveModel {
             id: NotSet,
@@ -49,6 +93,7 @@ impl Link {
             desc: Set(self.desc),
             source: Set(source.ty),
             source_id: Set(source.id),
+            source_chat_id: Set(source.chat_id),
             parsed: Set(false),
             packed: Set(None),
         }
@@ -1,16 +1,16 @@
 use std::fmt::{Display, Formatter};
 use std::time::Duration;
 
-use anyhow::Result;
-use grammers_client::grammers_tl_types::enums::MessageEntity;
+use anyhow::{anyhow, Result};
 use grammers_client::grammers_tl_types::functions::messages::GetBotCallbackAnswer;
 use grammers_client::grammers_tl_types::{self as tl, types::KeyboardButtonCallback};
 use grammers_client::Client;
 use sea_orm::entity::prelude::*;
 use sea_orm::Set;
-use tracing::{info, warn};
+use serde::{Deserialize, Serialize};
+use tracing::info;
 
-use super::{link, Source, SourceType};
+use super::{link, peer_id, unix_time, Source, SourceType};
 
 #[derive(Debug, Clone)]
 pub struct MessageExt {
@@ -41,30 +41,10 @@ impl MessageExt {
     }
 
     pub fn links(&self) -> Vec<link::Link> {
-        let mut ret = Vec::new();
-        let words: Vec<u16> = self.inner.raw.message.encode_utf16().collect();
-
-        if let Some(ref ents) = self.inner.raw.entities {
-            for ent in ents {
-                match ent {
-                    MessageEntity::TextUrl(url) => {
-                        let link = url.url.clone();
-
-                        let offset = url.offset as usize;
-                        let len = url.length as usize;
-
-                        if let Ok(desc) = String::from_utf16(&words[offset..offset + len]) {
-                            ret.push(link::Link { link, desc });
-                        } else {
-                            warn!("提取链接时错误");
-                        }
-                    }
-                    _ => (),
-                }
-            }
+        match self.inner.raw.entities {
+            Some(ref ents) => link::Link::from_entities(&self.inner.raw.message, ents),
+            None => Vec::new(),
         }
-
-        ret
     }
 
     pub fn callback_buttons(&self) -> Vec<KeyboardButtonCallback> {
@@ -109,7 +89,7 @@ impl MessageExt {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
 #[sea_orm(table_name = "message")]
 pub struct Model {
     #[sea_orm(primary_key)]
@@ -119,6 +99,22 @@ pub struct Model {
     pub raw: Json,
     pub source: SourceType,
     pub source_id: i64,
+    pub source_chat_id: Option<i64>,
+    pub text: Option<String>,
+    pub date: Option<DateTime>,
+    pub edit_date: Option<DateTime>,
+    pub sender_id: Option<i64>,
+    /// 转发来源的聊天或用户
+    pub fwd_from_chat: Option<i64>,
+    /// 转发来源频道中的消息编号
+    pub fwd_from_msg: Option<i32>,
+    pub reply_to: Option<i32>,
+    /// 同一相册的消息共享此值
+    pub grouped_id: Option<i64>,
+    pub views: Option<i32>,
+    pub forwards: Option<i32>,
+    pub post_author: Option<String>,
+    pub media_type: Option<String>,
     // TODO: add photo and video support
 }
 
@@ -127,16 +123,215 @@ pub enum Relation {}
 
 impl ActiveModelBehavior for ActiveModel {}
 
+impl Model {
+    pub fn source(&self) -> Source {
+        Source::new(self.source, self.source_id, self.source_chat_id)
+    }
+}
+
 impl ActiveModel {
-    pub fn from_inner_msg(msg: &grammers_client::types::Message, source: Source) -> Self {
-        let raw = Set(serde_json::to_value(&msg.raw).expect("message::ActiveModel:: from_inner_msg >> 传入的msg无效"));
-        Self {
+    pub fn from_inner_msg(msg: &grammers_client::types::Message, source: Source) -> Result<Self> {
+        let raw = serde_json::to_value(&msg.raw).map_err(|e| {
+            anyhow!(
+                "消息序列化失败 chat_id={} msg_id={} >> {}",
+                msg.chat().id(),
+                msg.id(),
+                e
+            )
+        })?;
+        let mut ret = Self {
             chat_id: Set(msg.chat().id()),
             msg_id: Set(msg.id()),
-            raw,
+            raw: Set(raw),
             source: Set(source.ty),
             source_id: Set(source.id),
+            source_chat_id: Set(source.chat_id),
             ..Default::default()
+        };
+        Columns::from_raw(&msg.raw).apply(&mut ret);
+        Ok(ret)
+    }
+}
+
+/// 由原始消息派生的规范化列
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Columns {
+    pub text: Option<String>,
+    pub date: Option<DateTime>,
+    pub edit_date: Option<DateTime>,
+    pub sender_id: Option<i64>,
+    pub fwd_from_chat: Option<i64>,
+    pub fwd_from_msg: Option<i32>,
+    pub reply_to: Option<i32>,
+    pub grouped_id: Option<i64>,
+    pub views: Option<i32>,
+    pub forwards: Option<i32>,
+    pub post_author: Option<String>,
+    pub media_type: Option<String>,
+}
+
+impl Columns {
+    pub fn from_raw(raw: &tl::types::Message) -> Self {
+        let (fwd_from_chat, fwd_from_msg) = match &raw.fwd_from {
+            Some(tl::enums::MessageFwdHeader::Header(h)) => {
+                (h.from_id.as_ref().map(peer_id), h.channel_post)
+            }
+            None => (None, None),
+        };
+        let reply_to = match &raw.reply_to {
+            Some(tl::enums::MessageReplyHeader::Header(h)) => h.reply_to_msg_id,
+            _ => None,
+        };
+        Self {
+            text: Some(raw.message.clone()),
+            date: Some(unix_time(raw.date)),
+            edit_date: raw.edit_date.map(unix_time),
+            // 频道消息没有发送者，视为频道本身发出
+            sender_id: raw
+                .from_id
+                .as_ref()
+                .map(peer_id)
+                .or_else(|| raw.post.then(|| peer_id(&raw.peer_id))),
+            fwd_from_chat,
+            fwd_from_msg,
+            reply_to,
+            grouped_id: raw.grouped_id,
+            views: raw.views,
+            forwards: raw.forwards,
+            post_author: raw.post_author.clone(),
+            media_type: raw.media.as_ref().map(|m| media_type(m).to_string()),
         }
     }
+
+    /// 由数据库中的raw列还原
+    pub fn from_json(raw: &Json) -> Result<Self> {
+        let raw: tl::types::Message = serde_json::from_value(raw.clone())?;
+        Ok(Self::from_raw(&raw))
+    }
+
+    pub fn apply(self, model: &mut ActiveModel) {
+        model.text = Set(self.text);
+        model.date = Set(self.date);
+        model.edit_date = Set(self.edit_date);
+        model.sender_id = Set(self.sender_id);
+        model.fwd_from_chat = Set(self.fwd_from_chat);
+        model.fwd_from_msg = Set(self.fwd_from_msg);
+        model.reply_to = Set(self.reply_to);
+        model.grouped_id = Set(self.grouped_id);
+        model.views = Set(self.views);
+        model.forwards = Set(self.forwards);
+        model.post_author = Set(self.post_author);
+        model.media_type = Set(self.media_type);
+    }
+}
+
+fn media_type(media: &tl::enums::MessageMedia) -> &'static str {
+    use tl::enums::MessageMedia as M;
+    match media {
+        M::Empty => "empty",
+        M::Photo(_) => "photo",
+        M::Document(_) => "document",
+        M::WebPage(_) => "webpage",
+        M::Geo(_) | M::GeoLive(_) => "geo",
+        M::Venue(_) => "venue",
+        M::Contact(_) => "contact",
+        M::Poll(_) => "poll",
+        M::Dice(_) => "dice",
+        M::Game(_) => "game",
+        M::Invoice(_) => "invoice",
+        M::Unsupported => "unsupported",
+        _ => "other",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    /// 以序列化形式构造原始消息，未给出的可选字段为空
+    fn raw_message(extra: Value) -> tl::types::Message {
+        let mut raw = json!({
+            "out": false,
+            "mentioned": false,
+            "media_unread": false,
+            "silent": false,
+            "post": false,
+            "from_scheduled": false,
+            "legacy": false,
+            "edit_hide": false,
+            "pinned": false,
+            "noforwards": false,
+            "invert_media": false,
+            "offline": false,
+            "video_processing_pending": false,
+            "id": 10,
+            "peer_id": {"Channel": {"channel_id": 100}},
+            "date": 1_700_000_000,
+            "message": "hello",
+        });
+        raw.as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        serde_json::from_value(raw).expect("消息反序列化")
+    }
+
+    #[test]
+    fn channel_post_is_sent_by_channel() {
+        let raw = raw_message(json!({"post": true, "views": 5, "forwards": 2}));
+        let columns = Columns::from_raw(&raw);
+        assert_eq!(columns.sender_id, Some(100));
+        assert_eq!(columns.text.as_deref(), Some("hello"));
+        assert_eq!(columns.date, Some(unix_time(1_700_000_000)));
+        assert_eq!(columns.views, Some(5));
+        assert_eq!(columns.forwards, Some(2));
+        assert_eq!(columns.edit_date, None);
+        assert_eq!(columns.media_type, None);
+    }
+
+    #[test]
+    fn group_message_keeps_sender() {
+        let raw = raw_message(json!({"from_id": {"User": {"user_id": 7}}}));
+        assert_eq!(Columns::from_raw(&raw).sender_id, Some(7));
+
+        // 非频道消息没有发送者时保持为空
+        let raw = raw_message(json!({}));
+        assert_eq!(Columns::from_raw(&raw).sender_id, None);
+    }
+
+    #[test]
+    fn forward_reply_and_album() {
+        let raw = raw_message(json!({
+            "from_id": {"User": {"user_id": 7}},
+            "fwd_from": {"Header": {
+                "imported": false,
+                "saved_out": false,
+                "from_id": {"Channel": {"channel_id": 200}},
+                "date": 1_600_000_000,
+                "channel_post": 42,
+            }},
+            "reply_to": {"Header": {
+                "reply_to_scheduled": false,
+                "forum_topic": false,
+                "quote": false,
+                "reply_to_msg_id": 9,
+            }},
+            "grouped_id": 123,
+            "edit_date": 1_700_000_100,
+        }));
+        let columns = Columns::from_raw(&raw);
+        assert_eq!(columns.fwd_from_chat, Some(200));
+        assert_eq!(columns.fwd_from_msg, Some(42));
+        assert_eq!(columns.reply_to, Some(9));
+        assert_eq!(columns.grouped_id, Some(123));
+        assert_eq!(columns.edit_date, Some(unix_time(1_700_000_100)));
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let raw = raw_message(json!({"post": true}));
+        let value = serde_json::to_value(&raw).unwrap();
+        assert_eq!(Columns::from_json(&value).unwrap(), Columns::from_raw(&raw));
+    }
 }
@@ -1,10 +1,20 @@
+use chrono::{NaiveDateTime, Utc};
+use grammers_client::grammers_tl_types as tl;
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
 
+pub mod alert;
 pub mod chat;
+pub mod chat_edge;
+pub mod chat_event;
+pub mod forward_candidate;
 pub mod link;
 pub mod message;
 pub mod search;
+pub mod search_result;
+pub mod update_state;
+pub mod user;
+pub mod username_history;
 
 pub use link::Model;
 pub use message::MessageExt;
@@ -24,19 +34,29 @@ pub enum SourceType {
     Chat,
     #[sea_orm(string_value = "")]
     Manual,
+    /// 由Telegram Desktop导出文件导入
+    #[sea_orm(string_value = "import")]
+    Import,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Source {
     pub ty: SourceType,
     pub id: i64,
+    /// 来源为消息时，消息所在的聊天；消息编号仅在聊天内唯一
+    pub chat_id: Option<i64>,
 }
 
 impl Source {
+    pub fn new(ty: SourceType, id: i64, chat_id: Option<i64>) -> Self {
+        Self { ty, id, chat_id }
+    }
+
     pub fn from_search(search: &search::Model) -> Self {
         Self {
             ty: SourceType::Search,
             id: search.id.into(),
+            chat_id: None,
         }
     }
 
@@ -44,13 +64,15 @@ impl Source {
         Self {
             ty: SourceType::Link,
             id: link.id.into(),
+            chat_id: None,
         }
     }
 
-    pub fn from_message(msg_id: i32) -> Self {
+    pub fn from_message(chat_id: i64, msg_id: i32) -> Self {
         Self {
             ty: SourceType::Message,
             id: msg_id.into(),
+            chat_id: Some(chat_id),
         }
     }
 
@@ -58,6 +80,16 @@ impl Source {
         Self {
             ty: SourceType::Chat,
             id: chat_id,
+            chat_id: None,
+        }
+    }
+
+    /// `chat_id`为导出文件中的聊天
+    pub fn from_import(chat_id: i64) -> Self {
+        Self {
+            ty: SourceType::Import,
+            id: chat_id,
+            chat_id: None,
         }
     }
 
@@ -65,6 +97,23 @@ impl Source {
         Self {
             ty: SourceType::Manual,
             id: -1,
+            chat_id: None,
         }
     }
 }
+
+/// 用户、群组或频道的id
+pub fn peer_id(peer: &tl::enums::Peer) -> i64 {
+    match peer {
+        tl::enums::Peer::User(p) => p.user_id,
+        tl::enums::Peer::Chat(p) => p.chat_id,
+        tl::enums::Peer::Channel(p) => p.channel_id,
+    }
+}
+
+/// Telegram的unix时间戳，超出范围时取当前时间
+pub fn unix_time(ts: i32) -> NaiveDateTime {
+    chrono::DateTime::from_timestamp(ts.into(), 0)
+        .unwrap_or_else(Utc::now)
+        .naive_utc()
+}
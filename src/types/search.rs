@@ -1,6 +1,7 @@
 use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, DeriveEntityModel)]
+#[derive(Clone, Debug, DeriveEntityModel, Serialize, Deserialize)]
 #[sea_orm(table_name = "search")]
 pub struct Model {
     #[sea_orm(primary_key)]
@@ -8,6 +9,19 @@ pub struct Model {
     pub bot: String,
     pub start_time: DateTime,
     pub keyword: String,
+    /// 已处理的结果页数
+    pub pages: i32,
+    /// 结果中的链接总数
+    pub results: i32,
+    /// 首次发现的链接数
+    pub new_links: i32,
+    /// 此前已存储的链接数
+    pub known_links: i32,
+    /// 已到最后一页或某页没有新链接
+    pub completed: bool,
+    pub end_time: Option<DateTime>,
+    /// 翻到最后一页，不因某页没有新链接提前结束；只有此类搜索参与前后比较
+    pub exhaustive: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -0,0 +1,19 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// 一次搜索结果中出现的链接，用于比较同一关键词的各次搜索
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "search_result")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub search_id: i32,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub link_id: i32,
+    /// 链接由此次搜索首次发现
+    pub is_new: bool,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
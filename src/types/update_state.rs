@@ -0,0 +1,72 @@
+use grammers_client::session::{enums, types};
+use sea_orm::{entity::prelude::*, Set};
+
+/// 公共更新状态所在行的`channel_id`
+pub const COMMON_STATE: i64 = 0;
+
+/// 更新状态，`channel_id`为[`COMMON_STATE`]的行保存pts/qts/date/seq，
+/// 其余每行保存一个频道的pts
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "update_state")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub channel_id: i64,
+    pub pts: i32,
+    pub qts: i32,
+    pub date: i32,
+    pub seq: i32,
+    pub updated: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl ActiveModel {
+    pub fn from_state(state: &types::UpdateState) -> Vec<Self> {
+        let updated = chrono::Utc::now().naive_utc();
+        let mut ret = vec![Self {
+            channel_id: Set(COMMON_STATE),
+            pts: Set(state.pts),
+            qts: Set(state.qts),
+            date: Set(state.date),
+            seq: Set(state.seq),
+            updated: Set(updated),
+        }];
+        for channel in state.channels.iter() {
+            let enums::ChannelState::State(channel) = channel;
+            ret.push(Self {
+                channel_id: Set(channel.channel_id),
+                pts: Set(channel.pts),
+                qts: Set(0),
+                date: Set(0),
+                seq: Set(0),
+                updated: Set(updated),
+            });
+        }
+        ret
+    }
+}
+
+/// 由全部行还原更新状态，缺少公共状态时返回None
+pub fn to_state(models: Vec<Model>) -> Option<types::UpdateState> {
+    let common = models.iter().find(|m| m.channel_id == COMMON_STATE)?;
+    let channels = models
+        .iter()
+        .filter(|m| m.channel_id != COMMON_STATE)
+        .map(|m| {
+            enums::ChannelState::State(types::ChannelState {
+                channel_id: m.channel_id,
+                pts: m.pts,
+            })
+        })
+        .collect();
+    Some(types::UpdateState {
+        pts: common.pts,
+        qts: common.qts,
+        date: common.date,
+        seq: common.seq,
+        channels,
+    })
+}
@@ -0,0 +1,79 @@
+use chrono::Utc;
+use grammers_client::grammers_tl_types as tl;
+use sea_orm::{entity::prelude::*, Set};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "user")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub user_id: i64,
+    pub usernames: Vec<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub is_bot: bool,
+    pub is_scam: bool,
+    pub is_fake: bool,
+    pub premium: bool,
+    /// 仅在对方公开或为联系人时可见
+    pub phone: Option<String>,
+    pub first_seen: DateTime,
+    pub last_seen: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl ActiveModel {
+    pub fn from_user(user: &grammers_client::types::User) -> Self {
+        let raw = &user.raw;
+        let now = Utc::now().naive_utc();
+        Self {
+            user_id: Set(raw.id),
+            usernames: Set(usernames(raw)),
+            first_name: Set(raw.first_name.clone()),
+            last_name: Set(raw.last_name.clone()),
+            is_bot: Set(raw.bot),
+            is_scam: Set(raw.scam),
+            is_fake: Set(raw.fake),
+            premium: Set(raw.premium),
+            phone: Set(raw.phone.clone()),
+            first_seen: Set(now),
+            last_seen: Set(now),
+        }
+    }
+}
+
+impl ActiveModel {
+    /// 消息发送者为用户时返回，第二项为是否为只含部分字段的`min`用户
+    pub fn from_sender(msg: &grammers_client::types::Message) -> Option<(Self, bool)> {
+        match msg.sender()? {
+            grammers_client::types::Chat::User(user) => {
+                Some((Self::from_user(&user), user.raw.min))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Model {
+    /// 用户名或姓名与记录不同
+    pub fn renamed(&self, other: &Model) -> bool {
+        self.usernames != other.usernames
+            || self.first_name != other.first_name
+            || self.last_name != other.last_name
+    }
+}
+
+/// 主用户名在前，其后为收藏用户名
+fn usernames(raw: &tl::types::User) -> Vec<String> {
+    let mut ret: Vec<String> = raw.username.iter().cloned().collect();
+    for tl::enums::Username::Username(name) in raw.usernames.iter().flatten() {
+        if name.active && !ret.contains(&name.username) {
+            ret.push(name.username.clone());
+        }
+    }
+    ret
+}
@@ -0,0 +1,35 @@
+use sea_orm::{entity::prelude::*, ActiveValue::NotSet, Set};
+
+use super::user;
+
+/// 用户名与姓名的变更历史，每次变化记录一行
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "username_history")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    pub user_id: i64,
+    pub usernames: Vec<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    /// 首次观察到此名称的时间
+    pub seen: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl ActiveModel {
+    pub fn from_user(user: &user::Model) -> Self {
+        Self {
+            id: NotSet,
+            user_id: Set(user.user_id),
+            usernames: Set(user.usernames.clone()),
+            first_name: Set(user.first_name.clone()),
+            last_name: Set(user.last_name.clone()),
+            seen: Set(user.last_seen),
+        }
+    }
+}
@@ -1,13 +1,35 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
 use anyhow::Result;
 use async_trait::async_trait;
-use grammers_client::{types::Message as RawMessage, Update};
+use grammers_client::{
+    grammers_tl_types as tl,
+    types::{CallbackQuery, InlineQuery, Message as RawMessage, MessageDeletion, PackedChat},
+    Update,
+};
 use tokio::{
-    sync::broadcast::{self, Receiver},
+    sync::mpsc::{
+        self,
+        error::{SendError, TrySendError},
+    },
     task::JoinSet,
 };
-use tracing::warn;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    app::History,
+    context::Context,
+    filter::Filter,
+    metrics,
+    supervisor::RestartPolicy,
+    types::{
+        chat_event::{ChatEvent, ChatEventKind},
+        MessageExt,
+    },
+    PrintError, Runable,
+};
 
-use crate::{context::Context, types::MessageExt, PrintError, Runable};
+static INCOMING: Filter = Filter::Incoming;
 
 /// 匹配器，以供部分实现
 #[async_trait]
@@ -15,106 +37,216 @@ pub trait Updater: Send + Sync + 'static {
     fn name(&self) -> &'static str;
 
     /// Occurs whenever a new text message or a message with media is produced.
-    async fn message_recv(&mut self, _context: Context, _msg: MessageExt) -> Result<()> {
+    async fn message_recv(&self, _context: Context, _msg: MessageExt) -> Result<()> {
         Ok(())
     }
 
     /// Occurs when a message is updated.
-    async fn message_edited(&mut self, _context: Context, _msg: MessageExt) -> Result<()> {
+    async fn message_edited(&self, _context: Context, _msg: MessageExt) -> Result<()> {
+        Ok(())
+    }
+
+    /// Occurs when one or more messages are deleted.
+    async fn message_deleted(&self, _context: Context, _deletion: MessageDeletion) -> Result<()> {
+        Ok(())
+    }
+
+    /// Occurs when a user presses an inline button of a message sent by this account.
+    async fn callback_query(&self, _context: Context, _query: CallbackQuery) -> Result<()> {
+        Ok(())
+    }
+
+    /// Occurs when a user sends an inline query to this account.
+    async fn inline_query(&self, _context: Context, _query: InlineQuery) -> Result<()> {
+        Ok(())
+    }
+
+    /// 成员加入、退出、权限变化与成员数跳变
+    async fn participant_changed(&self, _context: Context, _event: ChatEvent) -> Result<()> {
+        Ok(())
+    }
+
+    /// 聊天标题或头像变化
+    async fn chat_info_changed(&self, _context: Context, _event: ChatEvent) -> Result<()> {
+        Ok(())
+    }
+
+    async fn message_pinned(&self, _context: Context, _event: ChatEvent) -> Result<()> {
+        Ok(())
+    }
+
+    async fn reactions_updated(&self, _context: Context, _event: ChatEvent) -> Result<()> {
+        Ok(())
+    }
+
+    async fn views_updated(&self, _context: Context, _event: ChatEvent) -> Result<()> {
         Ok(())
     }
 
+    /// 投票结果变化，只在投票所在的消息已存储时触发
+    async fn poll_updated(&self, _context: Context, _event: ChatEvent) -> Result<()> {
+        Ok(())
+    }
+
+    /// 不属于以上类别的服务消息，服务消息同时也会触发[`Updater::message_recv`]
+    async fn service_message(&self, _context: Context, _event: ChatEvent) -> Result<()> {
+        Ok(())
+    }
+
+    /// 未被以上任何钩子处理的原始更新
+    async fn raw_update(&self, _context: Context, _update: tl::enums::Update) -> Result<()> {
+        Ok(())
+    }
+
+    /// 按事件类别调用对应的钩子
+    async fn chat_event(&self, context: Context, event: ChatEvent) -> Result<()> {
+        match event.kind {
+            ChatEventKind::Participant | ChatEventKind::MemberCount => {
+                self.participant_changed(context, event).await
+            }
+            ChatEventKind::Title | ChatEventKind::Photo => {
+                self.chat_info_changed(context, event).await
+            }
+            ChatEventKind::Pinned => self.message_pinned(context, event).await,
+            ChatEventKind::Reactions => self.reactions_updated(context, event).await,
+            ChatEventKind::Views => self.views_updated(context, event).await,
+            ChatEventKind::Poll => self.poll_updated(context, event).await,
+            ChatEventKind::Service => self.service_message(context, event).await,
+        }
+    }
+
     /// DO NOT RELOAD THIS FUNCTION
     /// UNLESS YOU KNOW WHAT YOU DO
     ///
+    /// 过滤已由分发器通过[`Updater::accepts`]完成
+    ///
     /// * return Some(()) if parsed;
     /// * return None if not parsed
     ///
     /// Every error should be parsed inside this function.
-    async fn parse_update(&mut self, context: Context, update: Update) -> Option<()> {
+    async fn parse_update(&self, context: Context, update: Update) -> Option<()> {
         let result = {
             match update {
                 Update::NewMessage(ref raw_msg) => {
-                    if self.raw_msg_filter(raw_msg) {
-                        Some(self.message_recv(context, raw_msg.into()).await)
-                    } else {
-                        None
+                    let mut ret = self.message_recv(context.clone(), raw_msg.into()).await;
+                    if let Some(event) = ChatEvent::from_service(raw_msg) {
+                        ret = ret.and(self.chat_event(context, event).await);
                     }
+                    Some(ret)
                 }
                 Update::MessageEdited(ref raw_msg) => {
-                    if self.raw_msg_filter(raw_msg) {
-                        Some(self.message_edited(context, raw_msg.into()).await)
-                    } else {
-                        None
-                    }
+                    Some(self.message_edited(context, raw_msg.into()).await)
                 }
-                Update::Raw(_) => None,
+                Update::MessageDeleted(deletion) => {
+                    Some(self.message_deleted(context, deletion).await)
+                }
+                Update::CallbackQuery(query) => Some(self.callback_query(context, query).await),
+                Update::InlineQuery(query) => Some(self.inline_query(context, query).await),
+                Update::Raw(raw) => match ChatEvent::from_raw(&raw) {
+                    Some(event) => Some(match attribute_poll(&context, event).await {
+                        Ok(Some(event)) => self.chat_event(context, event).await,
+                        other => other.map(|_| ()),
+                    }),
+                    None => Some(self.raw_update(context, raw).await),
+                },
                 _ => None,
             }
         };
         result.and_then(|some| some.ok_or_log())
     }
 
-    /// default implement will fliter all message that incoming
-    /// using `!raw_msg.outgoing()`
+    /// 分发器据此决定是否将更新送入此解析器的队列
     ///
-    /// * return true this message will get parsed later;
-    /// * return false will ignore this message
-    fn raw_msg_filter(&self, raw_msg: &RawMessage) -> bool {
-        let mut flag = true;
-
-        if self.filter_incoming() {
-            raw_msg.outgoing().then(|| flag = false);
-        }
-
-        if let Some(id) = self.filter_chat_id() {
-            if raw_msg.chat().id() != id {
-                flag = false;
+    /// 消息经过[`Updater::filter`]，其他更新由[`Updater::accept_events`]决定
+    fn accepts(&self, update: &Update) -> bool {
+        match update {
+            Update::NewMessage(raw_msg) | Update::MessageEdited(raw_msg) => {
+                self.raw_msg_filter(raw_msg)
             }
+            _ => self.accept_events(),
         }
+    }
 
-        if let Some(word) = self.filter_word() {
-            if !raw_msg.text().contains(&word) {
-                flag = false
-            }
-        }
+    /// 是否接收消息以外的更新，默认不接收
+    fn accept_events(&self) -> bool {
+        false
+    }
 
-        flag
+    /// 消息过滤器，默认只接收非本账号发出的消息
+    fn filter(&self) -> &Filter {
+        &INCOMING
     }
 
-    fn filter_incoming(&self) -> bool {
-        true
+    /// * return true this message will get parsed later;
+    /// * return false will ignore this message
+    fn raw_msg_filter(&self, raw_msg: &RawMessage) -> bool {
+        self.filter().matches(raw_msg)
     }
 
-    fn filter_chat_id(&self) -> Option<i64> {
-        None
+    /// 队列满载时是否丢弃更新，稍后用[`History`]补齐受影响聊天的消息
+    ///
+    /// 默认不丢弃，分发器等待队列空出
+    fn resync_on_lag(&self) -> bool {
+        false
     }
 
-    fn filter_word(&self) -> Option<String> {
-        None
+    /// 并行处理的队列数量，同一聊天的更新总是进入同一队列，保证顺序
+    fn concurrency(&self) -> usize {
+        1
+    }
+
+    /// 单个队列的容量
+    fn queue_size(&self) -> usize {
+        256
     }
 
     // fn filter_text(&self)
 }
 
+/// 补齐被丢弃更新的周期
+pub const RESYNC_INTERVAL: Duration = Duration::from_secs(60);
+/// 单个聊天补齐的最大消息数量
+pub const RESYNC_LIMIT: usize = 10000;
+/// 更新状态写入数据库的周期
+pub const UPDATE_STATE_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 更新分发器
+///
+/// 每个更新只对每个解析器过滤一次，通过后按聊天分配到该解析器的有界队列中：
+/// 同一聊天顺序处理，不同聊天并行处理。队列满时，声明了[`Updater::resync_on_lag`]的解析器
+/// 丢弃该更新并记录聊天，稍后补齐，不拖慢其他解析器；其他解析器等待队列空出，不丢失更新。
 pub struct UpdateApp {
-    parser: Vec<UpdateParser>,
-    tx: broadcast::Sender<Update>,
-    #[allow(dead_code)] // 确保至少存在一个rx与tx，防止channel关闭
-    rx: broadcast::Receiver<Update>,
+    parser: Vec<Arc<dyn Updater>>,
+    routes: Vec<Route>,
+    workers: JoinSet<()>,
+    /// 因队列满载而丢弃过更新的聊天，等待补齐
+    resync: HashMap<i64, PackedChat>,
 }
 impl UpdateApp {
     pub fn new() -> Self {
-        let (tx, rx) = broadcast::channel(2048);
         Self {
             parser: Vec::new(),
-            tx,
-            rx,
+            routes: Vec::new(),
+            workers: JoinSet::new(),
+            resync: HashMap::new(),
         }
     }
     pub fn add_parser(&mut self, parser: impl Updater) -> () {
-        let rt = UpdateParser::new(self.tx.subscribe(), parser);
-        self.parser.push(rt);
+        self.parser.push(Arc::new(parser));
+    }
+
+    async fn flush_resync(&mut self, ctx: &Context) {
+        for (chat_id, packed) in self.resync.drain() {
+            let last_msg_id = ctx
+                .persist
+                .find_last_msg_id(chat_id)
+                .await
+                .ok_or_log()
+                .flatten();
+            warn!(chat_id, last_msg_id, "补齐聊天记录");
+            ctx.add_runable(History::after(packed, last_msg_id, RESYNC_LIMIT))
+                .await;
+        }
     }
 }
 impl Default for UpdateApp {
@@ -127,70 +259,204 @@ impl Runable for UpdateApp {
     fn name(&self) -> &'static str {
         "更新"
     }
+
+    /// 出错退出后由监督者重启，已有的队列与处理任务保持不变
     async fn run(&mut self, ctx: Context) -> Result<()> {
-        let mut count = 0;
-        loop {
-            let mut tasks = JoinSet::new();
-            while let Some(mut i) = self.parser.pop() {
-                let ctxx = ctx.clone();
-                tasks.spawn(async move { i.run(ctxx).await });
+        // 启动解析器
+        while let Some(parser) = self.parser.pop() {
+            self.routes
+                .push(Route::new(parser, &ctx, &mut self.workers));
+        }
+
+        let mut resync = tokio::time::interval(RESYNC_INTERVAL);
+        // 跨循环保留进行中的`next_update`，计时器分支不会丢弃它
+        let mut next_update = Box::pin(ctx.client.next_update());
+        let result = loop {
+            let update = tokio::select! {
+                update = &mut next_update => update,
+                _ = resync.tick() => {
+                    self.flush_resync(&ctx).await;
+                    continue;
+                }
+                _ = ctx.cancel.cancelled() => break Ok(()),
+            };
+            next_update = Box::pin(ctx.client.next_update());
+            let update = match update {
+                Ok(update) => update,
+                Err(e) => break Err(e.into()),
+            };
+            let chat_id = update_chat_id(&update);
+            for route in self.routes.iter_mut() {
+                if let Some(lagged) = route
+                    .dispatch(&ctx, &update, chat_id, &mut self.workers)
+                    .await
+                {
+                    self.resync.insert(lagged.id, lagged);
+                }
             }
-            let mut listener = UpdateListener::new(self.tx.clone());
-            let ctxx = ctx.clone();
-            tasks.spawn(async move { listener.run(ctxx).await });
-            while let Some(task) = tasks.join_next().await {
-                task?.into_log();
+        };
+
+        if ctx.cancel.is_cancelled() {
+            // 关闭队列，等待处理任务处理完队列中剩余的更新
+            self.routes.clear();
+            while let Some(task) = self.workers.join_next().await {
+                if let Err(e) = task {
+                    error!("{}", e);
+                }
             }
-            count += 1;
-            warn!(count, "Update守护进程过早退出，自动重启")
         }
+        result
     }
-}
 
-pub struct UpdateParser {
-    inner: Box<dyn Updater>,
-    rx: broadcast::Receiver<Update>,
-}
-impl UpdateParser {
-    pub fn new(update_receiver: Receiver<Update>, parser: impl Updater) -> Self {
-        Self {
-            inner: Box::new(parser),
-            rx: update_receiver,
-        }
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::always()
     }
 }
+
+/// 定时将更新状态写入数据库，与分发器分开运行
+pub struct SaveUpdateState;
+
 #[async_trait]
-impl Runable for UpdateParser {
+impl Runable for SaveUpdateState {
     fn name(&self) -> &'static str {
-        self.inner.name()
+        "更新状态保存"
     }
 
     async fn run(&mut self, ctx: Context) -> Result<()> {
-        while let Ok(update) = self.rx.recv().await {
-            self.inner.parse_update(ctx.clone(), update).await;
+        let mut save_state = tokio::time::interval(UPDATE_STATE_SAVE_INTERVAL);
+        save_state.tick().await;
+        loop {
+            tokio::select! {
+                _ = save_state.tick() => {
+                    ctx.save_update_state().await.ok_or_warn();
+                }
+                // 退出时由`Context::run`保存
+                _ = ctx.cancel.cancelled() => return Ok(()),
+            }
         }
-        Ok(())
+    }
+
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::always()
     }
 }
 
-pub struct UpdateListener {
-    tx: broadcast::Sender<Update>,
+/// 单个解析器的分发路由
+struct Route {
+    parser: Arc<dyn Updater>,
+    shards: Vec<mpsc::Sender<Update>>,
 }
-impl UpdateListener {
-    pub fn new(update_sender: broadcast::Sender<Update>) -> Self {
-        Self { tx: update_sender }
+impl Route {
+    fn new(parser: Arc<dyn Updater>, ctx: &Context, workers: &mut JoinSet<()>) -> Self {
+        let mut ret = Self {
+            shards: Vec::new(),
+            parser,
+        };
+        for _ in 0..ret.parser.concurrency().max(1) {
+            let tx = ret.spawn_worker(ctx, workers);
+            ret.shards.push(tx);
+        }
+        info!(
+            parser = ret.parser.name(),
+            shards = ret.shards.len(),
+            "启动解析器"
+        );
+        ret
     }
-}
-#[async_trait]
-impl Runable for UpdateListener {
-    fn name(&self) -> &'static str {
-        "更新监听器"
+
+    fn spawn_worker(&self, ctx: &Context, workers: &mut JoinSet<()>) -> mpsc::Sender<Update> {
+        let (tx, mut rx) = mpsc::channel(self.parser.queue_size());
+        let parser = self.parser.clone();
+        let ctx = ctx.clone();
+        // 退出时分发器关闭队列，处理完剩余更新后结束
+        workers.spawn(async move {
+            while let Some(update) = rx.recv().await {
+                parser.parse_update(ctx.clone(), update).await;
+            }
+        });
+        tx
     }
 
-    async fn run(&mut self, ctx: Context) -> Result<()> {
-        while let Ok(update) = ctx.client.next_update().await {
-            self.tx.send(update)?;
+    /// 返回因队列满载而丢弃了更新、需要补齐的聊天
+    async fn dispatch(
+        &mut self,
+        ctx: &Context,
+        update: &Update,
+        chat_id: Option<i64>,
+        workers: &mut JoinSet<()>,
+    ) -> Option<PackedChat> {
+        if !self.parser.accepts(update) {
+            return None;
         }
-        Ok(())
+        let name = self.parser.name();
+        let resync = self.parser.resync_on_lag();
+        let shard = chat_id
+            .map(|id| id.rem_euclid(self.shards.len() as i64) as usize)
+            .unwrap_or(0);
+
+        let mut pending = update.clone();
+        let ret = loop {
+            match self.shards[shard].try_send(pending) {
+                Ok(()) => break None,
+                Err(TrySendError::Full(_)) if resync => {
+                    metrics::UPDATE_LAGGED.with_label_values(&[name]).inc();
+                    warn!(parser = name, shard, "解析器处理过慢，更新丢失，稍后补齐");
+                    break update_chat(update);
+                }
+                Err(TrySendError::Full(full)) => {
+                    warn!(parser = name, shard, "解析器处理过慢，等待队列空出");
+                    match self.shards[shard].send(full).await {
+                        Ok(()) => break None,
+                        Err(SendError(closed)) => pending = closed,
+                    }
+                }
+                Err(TrySendError::Closed(closed)) => pending = closed,
+            }
+            // 队列已关闭，重新启动处理任务后再次发送
+            error!(parser = name, shard, "处理任务意外退出，重新启动");
+            self.shards[shard] = self.spawn_worker(ctx, workers);
+        };
+
+        let queued: usize = self
+            .shards
+            .iter()
+            .map(|tx| tx.max_capacity() - tx.capacity())
+            .sum();
+        metrics::UPDATE_QUEUE_LEN
+            .with_label_values(&[name])
+            .set(queued as i64);
+        ret
+    }
+}
+
+/// 投票更新只携带投票id，由已存储的投票消息确定所属聊天，找不到时返回None
+async fn attribute_poll(context: &Context, mut event: ChatEvent) -> Result<Option<ChatEvent>> {
+    let Some(poll_id) = event.poll_id else {
+        return Ok(Some(event));
+    };
+    let Some(msg) = context.persist.find_poll_message(poll_id).await? else {
+        debug!(poll_id, "投票所在的消息未存储，忽略");
+        return Ok(None);
+    };
+    event.chat_id = msg.chat_id;
+    event.msg_id = Some(msg.msg_id);
+    Ok(Some(event))
+}
+
+/// 更新所属的聊天
+fn update_chat(update: &Update) -> Option<PackedChat> {
+    match update {
+        Update::NewMessage(raw_msg) | Update::MessageEdited(raw_msg) => Some(raw_msg.chat().pack()),
+        _ => None,
+    }
+}
+
+/// 更新所属的聊天id，用于保证同一聊天的更新顺序
+fn update_chat_id(update: &Update) -> Option<i64> {
+    match update {
+        Update::NewMessage(raw_msg) | Update::MessageEdited(raw_msg) => Some(raw_msg.chat().id()),
+        Update::MessageDeleted(deletion) => deletion.channel_id(),
+        Update::Raw(raw) => ChatEvent::raw_chat_id(raw),
+        _ => None,
     }
 }